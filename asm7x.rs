@@ -12,57 +12,263 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+use std::fmt;
+
+mod cdp1802;
+mod cp1610;
+mod f8;
+mod i8048;
+mod i8086;
+mod ines;
+mod mos6502;
+mod output;
+mod s2650;
+mod simulator;
+#[cfg(test)]
+mod tests;
+mod tms9900;
+mod vcs;
+
+use crate::cdp1802::Cdp1802Assembler;
+use crate::cp1610::Cp1610Assembler;
+use crate::f8::F8Assembler;
+use crate::i8048::I8048Assembler;
+use crate::i8086::I8086Assembler;
+use crate::mos6502::{Mos6502Assembler, Variant};
+use crate::output::Image;
+use crate::s2650::S2650Assembler;
+use crate::simulator::{Cpu, Test};
+use crate::tms9900::Tms9900Assembler;
+
+// asm7x disasm file address [processor] prints the source of a binary
+// loaded at an address, for the 6502 family
+fn disassemble(args: &[String]) {
+    let (path, origin) = match args {
+        [path, origin] | [path, origin, _] => (path, origin),
+        _ => {
+            println!("usage: asm7x disasm file address [processor]");
+            panic!("unimplemented error handling")
+        }
+    };
+    let origin = match origin.strip_prefix('$').or(origin.strip_prefix("0x")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => origin.parse().ok(),
+    };
+    let processor = args.get(2).map_or("6502", |p| p.as_str());
+    let variant = match Variant::named(processor) {
+        Some(v) => v,
+        None => {
+            println!("cannot disassemble for processor: {}", processor);
+            panic!("unimplemented error handling")
+        }
+    };
+    // The origin becomes an org directive
+    let mut disassembler = Mos6502Assembler::new(variant);
+    let origin = match origin {
+        Some(o) if o <= disassembler.max_address() => o,
+        _ => {
+            println!("invalid address");
+            panic!("unimplemented error handling")
+        }
+    };
+    let bytes = match std::fs::read(path) {
+        Ok(b) => b,
+        Err(e) => {
+            println!("cannot read {}: {}", path, e);
+            panic!("unimplemented error handling")
+        }
+    };
+    print!("{}", disassembler.disassemble(processor, &bytes, origin));
+}
+
+// Read a source file, which must end with a newline
+fn read_source(path: &str) -> String {
+    let mut source = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("cannot read {}: {}", path, e);
+            panic!("unimplemented error handling")
+        }
+    };
+    if !source.ends_with('\n') {
+        source.push('\n');
+    }
+    source
+}
+
+// The variant that the simulator runs an image on
+fn simulated(image: &Image) -> Variant {
+    match Variant::named(&image.processor) {
+        Some(v) if v != Variant::W65816 => v,
+        _ => {
+            println!("cannot simulate processor: {}", image.processor);
+            panic!("unimplemented error handling")
+        }
+    }
+}
+
+// asm7x sim file label [cycles] assembles a source for the 6502 family
+// and runs it from a label, until BRK, RTS, or a million cycles
+fn simulate(args: &[String]) {
+    let (path, label) = match args {
+        [path, label] | [path, label, _] => (path, label),
+        _ => {
+            println!("usage: asm7x sim file label [cycles]");
+            panic!("unimplemented error handling")
+        }
+    };
+    let limit = match args.get(2).map_or(Some(1_000_000), |c| c.parse().ok()) {
+        Some(l) => l,
+        None => {
+            println!("invalid cycle count");
+            panic!("unimplemented error handling")
+        }
+    };
+    let image = assemble(&read_source(path), path);
+    let variant = simulated(&image);
+    let address = match image.symbols.get(label) {
+        Some(&a) => a,
+        None => {
+            println!("unknown label: {}", label);
+            panic!("unimplemented error handling")
+        }
+    };
+    let mut cpu = Cpu::new(variant);
+    cpu.load(&image);
+    let stop = cpu.call((address & 0xFFFF) as u16, limit);
+    println!("# {:?} after {} cycles", stop, cpu.cycles);
+    println!(
+        "# A={:02X} X={:02X} Y={:02X} S={:02X} P={:02X} PC={:04X}",
+        cpu.a, cpu.x, cpu.y, cpu.s, cpu.p, cpu.pc
+    );
+}
+
+// asm7x test file assembles a source for the 6502 family and runs its
+// test blocks, exiting with 1 when any fails
+fn run_tests(args: &[String]) {
+    let path = match args {
+        [path] => path,
+        _ => {
+            println!("usage: asm7x test file");
+            panic!("unimplemented error handling")
+        }
+    };
+    let image = assemble(&read_source(path), path);
+    let variant = simulated(&image);
+    let mut failed = 0;
+    for t in &image.tests {
+        match t.run(&image, variant) {
+            Ok(()) => println!("# pass: {}", t.name),
+            Err(e) => {
+                println!("# FAIL: {}: {}", t.name, e);
+                failed += 1;
+            }
+        }
+    }
+    println!("# {} passed, {} failed", image.tests.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|a| a.as_str()) {
+        Some("disasm") => return disassemble(&args[1..]),
+        Some("sim") => return simulate(&args[1..]),
+        Some("test") => return run_tests(&args[1..]),
+        _ => (),
+    }
+    let mut path = None;
+    let mut output = None;
+    let mut record_length = 16;
+    let mut args = args.into_iter();
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "-o" => output = args.next(),
+            "--record-length" => {
+                record_length = match args.next().and_then(|l| l.parse().ok()) {
+                    Some(l @ 1..=255) => l,
+                    _ => {
+                        println!("invalid record length");
+                        panic!("unimplemented error handling")
+                    }
+                }
+            }
+            _ => path = Some(a),
+        }
+    }
     let mut source = String::from("");
-    source.push_str(" processor 6502\n");
-    source.push_str(" org 32752\n");
-    source.push_str(" byte 78\n");
-    source.push_str(" byte 69\n");
-    source.push_str(" byte 83\n");
-    source.push_str(" byte 26\n");
-    source.push_str(" byte 2\n");
-    source.push_str(" byte 1\n");
-    source.push_str(" byte 1\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 0\n");
-    source.push_str("Reset:\n");
-    source.push_str("\tLDX\t#255\n");
-    source.push_str("\tTXS\t\t;set up stack\n");
-    source.push_str("\tCLD\n");
-    source.push_str("\tSEI\n");
-    source.push_str("\tBIT\t8194\n");
-    source.push_str("\tBCS\t32773\n");
-    source.push_str("\tBIT\t8194\n");
-    source.push_str("\tBCS\t32778\n");
-    source.push_str("\tLDA\t#0\n");
-    source.push_str("\tSTA\t8192\n");
-    source.push_str("\tSTA\t8193\n");
-    source.push_str("\tLDA\t#63\n");
-    source.push_str("\tSTA\t8198\n");
-    source.push_str("\tLDA\t#0\n");
-    source.push_str("\tSTA\t8198\n");
-    source.push_str("\tLDA\t#26\n");
-    source.push_str("\tSTA\t8199\n");
-    source.push_str("\tJMP\t32806\n");
-    source.push_str("\torg 65529\n");
-    source.push_str("\tRTI\n");
-    source.push_str(" byte 249\n");
-    source.push_str(" byte 255\n");
-    source.push_str(" byte 0\n");
-    source.push_str(" byte 128\n");
-    source.push_str(" byte 249\n");
-    source.push_str(" byte 255\n");
-    let mut assembler = Parser::new(&source);
+    let mut file = String::from("<builtin>");
+    if let Some(path) = &path {
+        source = read_source(path);
+        file = path.clone();
+    } else {
+        source.push_str(" processor 2a03\n");
+        source.push_str(" org 32768\n");
+        source.push_str(" ines.prg 2\n");
+        source.push_str(" ines.chr 1\n");
+        source.push_str(" ines.mapper 0\n");
+        source.push_str(" ines.mirroring vertical\n");
+        source.push_str("Reset:\n");
+        source.push_str("\tLDX\t#255\n");
+        source.push_str("\tTXS\t\t;set up stack\n");
+        source.push_str("\tCLD\n");
+        source.push_str("\tSEI\n");
+        source.push_str("\tBIT\tPPUSTATUS\n");
+        source.push_str("\tBCS\t32773\n");
+        source.push_str("\tBIT\tPPUSTATUS\n");
+        source.push_str("\tBCS\t32778\n");
+        source.push_str("\tLDA\t#0\n");
+        source.push_str("\tSTA\tPPUCTRL\n");
+        source.push_str("\tSTA\tPPUMASK\n");
+        source.push_str("\tLDA\t#63\n");
+        source.push_str("\tSTA\tPPUADDR\n");
+        source.push_str("\tLDA\t#0\n");
+        source.push_str("\tSTA\tPPUADDR\n");
+        source.push_str("\tLDA\t#26\n");
+        source.push_str("\tSTA\tPPUDATA\n");
+        source.push_str("\tJMP\t32806\n");
+        source.push_str("\torg 65529\n");
+        source.push_str("\tRTI\n");
+        source.push_str(" byte 249\n");
+        source.push_str(" byte 255\n");
+        source.push_str(" byte 0\n");
+        source.push_str(" byte 128\n");
+        source.push_str(" byte 249\n");
+        source.push_str(" byte 255\n");
+        source.push_str(" chr 0\n");
+    }
+    let image = assemble(&source, &file);
+    if let Some(o) = output {
+        output::write(&image, &o, record_length);
+    }
+}
+
+// Parse and assemble a source, with the back end that its processor
+// directive selects
+fn assemble(source: &str, file: &str) -> Image {
+    let mut assembler = Parser::new(source, file);
     let parsed = assembler.parse_source();
     parsed.list();
-    Mos6502Assembler {src: parsed}.assemble();
+    let processor = parsed.processor();
+    match processor.as_str() {
+        p if Variant::named(p).is_some() => {
+            parsed.assemble(&mut Mos6502Assembler::new(Variant::named(p).unwrap()))
+        }
+        "1802" | "cdp1802" => parsed.assemble(&mut Cdp1802Assembler {}),
+        "f8" | "3850" => parsed.assemble(&mut F8Assembler {}),
+        "cp1610" => parsed.assemble(&mut Cp1610Assembler::new()),
+        "8048" => parsed.assemble(&mut I8048Assembler::new()),
+        "2650" => parsed.assemble(&mut S2650Assembler {}),
+        "9900" | "tms9900" => parsed.assemble(&mut Tms9900Assembler {}),
+        "8086" | "8088" => parsed.assemble(&mut I8086Assembler::new()),
+        p => {
+            println!("unknown processor: {}", p);
+            panic!("unimplemented error handling")
+        }
+    }
 }
 
 struct ParsedSource {
@@ -70,18 +276,90 @@ struct ParsedSource {
 }
 
 struct ParsedLine {
+    line: u32,
     label: Option<String>,
     instruction: Option<Instruction>,
 }
 
 struct Instruction {
     mnemonic: String,
-    parameter: Option<Number>,
+    parameters: Vec<Expression>,
 }
 
-enum Number {
-    Immediate(i64),
-    Address(i64),
+enum Expression {
+    Immediate(Box<Expression>),
+    Indirect(Box<Expression>),
+    // '*' prefix, which marks indirect addressing on processors
+    // that use '@' for something else
+    Deferred(Box<Expression>),
+    // '+' and '-' suffixes, for auto-increment and auto-decrement
+    PostIncrement(Box<Expression>),
+    PostDecrement(Box<Expression>),
+    // Address followed by an index register in parentheses
+    Indexed(Box<Expression>, Box<Expression>),
+    // Memory operand in brackets
+    Memory(Box<Expression>),
+    // Segment and offset, separated by ':'
+    Segment(Box<Expression>, Box<Expression>),
+    // Name that qualifies the operand after it, e.g. BYTE [BX]
+    Qualified(String, Box<Expression>),
+    // Bank number of a label, bank(label)
+    Bank(String),
+    // String in double quotes
+    Text(String),
+    // target=value, in set and expect
+    Assignment(Box<Expression>, Box<Expression>),
+    // Addresses from the first to the last, e.g. mem[$20..$22]
+    Range(Box<Expression>, Box<Expression>),
+    // Values in brackets, e.g. [1,2,3]
+    List(Vec<Expression>),
+    Sum(Box<Expression>, Box<Expression>),
+    Difference(Box<Expression>, Box<Expression>),
+    Product(Box<Expression>, Box<Expression>),
+    Negation(Box<Expression>),
+    Parenthesized(Vec<Expression>),
+    Identifier(String),
+    Number(i64),
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Immediate(e) => write!(f, "#{}", e),
+            Expression::Indirect(e) => write!(f, "@{}", e),
+            Expression::Deferred(e) => write!(f, "*{}", e),
+            Expression::PostIncrement(e) => write!(f, "{}+", e),
+            Expression::PostDecrement(e) => write!(f, "{}-", e),
+            Expression::Indexed(a, r) => write!(f, "{}({})", a, r),
+            Expression::Memory(e) => write!(f, "[{}]", e),
+            Expression::Segment(s, e) => write!(f, "{}:{}", s, e),
+            Expression::Qualified(q, e) => write!(f, "{} {}", q, e),
+            Expression::Bank(s) => write!(f, "bank({})", s),
+            Expression::Text(s) => write!(f, "\"{}\"", s),
+            Expression::Assignment(t, v) => write!(f, "{}={}", t, v),
+            Expression::Range(a, b) => write!(f, "{}..{}", a, b),
+            Expression::Sum(a, b) => write!(f, "{}+{}", a, b),
+            Expression::Difference(a, b) => write!(f, "{}-{}", a, b),
+            Expression::Product(a, b) => write!(f, "{}*{}", a, b),
+            Expression::Negation(e) => write!(f, "-{}", e),
+            Expression::Parenthesized(l) | Expression::List(l) => {
+                let (open, close) = match self {
+                    Expression::List(_) => ("[", "]"),
+                    _ => ("(", ")"),
+                };
+                write!(f, "{}", open)?;
+                for (n, e) in l.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                write!(f, "{}", close)
+            }
+            Expression::Identifier(s) => write!(f, "{}", s),
+            Expression::Number(n) => write!(f, "{}", n),
+        }
+    }
 }
 
 impl ParsedSource {
@@ -93,378 +371,560 @@ impl ParsedSource {
             print!(" ");
             if let Some(i) = &line.instruction {
                 print!("{}", i.mnemonic);
-                if let Some(p) = &i.parameter {
-                    match p {
-                        crate::Number::Immediate(p) => print!(" #{}", p),
-                        crate::Number::Address(p) => print!(" {}", p),
+                for (n, p) in i.parameters.iter().enumerate() {
+                    if n == 0 {
+                        print!(" ");
+                    } else {
+                        print!(",");
                     }
+                    print!("{}", p);
                 }
             }
             println!();
         }
         println!();
     }
-}
 
-struct Mos6502Assembler {
-    src: ParsedSource,
-}
+    // Find which processor the source is written for
+    //
+    // That's the parameter of the first processor directive
+    fn processor(&self) -> String {
+        for line in &self.lines {
+            if let Some(i) = &line.instruction {
+                if i.mnemonic == "processor" {
+                    if let [Expression::Identifier(p)] = i.parameters.as_slice() {
+                        return p.clone();
+                    }
+                    println!("invalid parameter for processor at line {}", line.line);
+                    panic!("unimplemented error handling")
+                }
+            }
+        }
+        println!("missing processor directive");
+        panic!("unimplemented error handling")
+    }
 
-impl Mos6502Assembler {
-    fn assemble(&self) {
-        let mut address = 0_u32;
+    // Assemble the source with a given back end
+    //
+    // Run passes until the values of all labels are stable, which
+    // resolves forward references, then run a final pass that
+    // actually emits the code.
+    fn assemble(&self, backend: &mut dyn Backend) -> Image {
+        let mut symbols = HashMap::new();
+        let mut pass = 1;
+        loop {
+            println!("# assembly pass {}", pass);
+            let asm = self.assemble_pass(backend, symbols, false);
+            symbols = asm.symbols;
+            if !asm.changed {
+                break;
+            }
+            pass += 1;
+            if pass > MAX_PASSES {
+                println!("labels still moving after {} passes", MAX_PASSES);
+                panic!("unimplemented error handling")
+            }
+        }
         println!("#!/bin/bash");
-        for line in &self.src.lines {
+        let header = backend.header();
+        if !header.is_empty() {
+            println!("# emitting {} byte header", header.len());
+            print!("echo -en '");
+            for b in &header {
+                print!("\\x{:02x}", b);
+            }
+            println!("'");
+        }
+        let asm = self.assemble_pass(backend, symbols, true);
+        println!();
+        let scale = if asm.word_addressed { 2 } else { 1 };
+        let mut sections = asm.sections;
+        if sections.first().is_none_or(|s| s.1 > 0) {
+            sections.insert(0, (asm.origin.unwrap_or(0), 0, false));
+        }
+        Image {
+            origin: asm.origin.unwrap_or(0) * scale,
+            header,
+            bytes: asm.image,
+            sections: sections
+                .into_iter()
+                .map(|(a, o, f)| (a * scale, o, f))
+                .collect(),
+            inits: asm
+                .inits
+                .into_iter()
+                .map(|(o, a)| (o, a as u32 * scale))
+                .collect(),
+            entry: asm
+                .entry
+                .map(|(s, o)| (s.map(|s| s as u32), o as u32 * scale)),
+            segmented: backend.segmented(),
+            file_type: asm.file_type,
+            aux_type: asm.aux_type,
+            processor: self.processor(),
+            symbols: asm.symbols,
+            tests: asm.tests,
+        }
+    }
+
+    fn assemble_pass(
+        &self,
+        backend: &mut dyn Backend,
+        previous: HashMap<String, i64>,
+        final_pass: bool,
+    ) -> Assembly {
+        backend.start_pass();
+        let mut asm = Assembly {
+            address: 0,
+            word_addressed: backend.word_addressed(),
+            line: 0,
+            bank: None,
+            origin: None,
+            image: Vec::new(),
+            sections: Vec::new(),
+            inits: Vec::new(),
+            entry: None,
+            file_type: None,
+            aux_type: None,
+            tests: Vec::new(),
+            symbols: HashMap::new(),
+            previous,
+            changed: false,
+            final_pass,
+        };
+        for (name, value) in backend.symbols() {
+            asm.define(name, *value);
+        }
+        for line in &self.lines {
+            asm.line = line.line;
+            if let Some(l) = &line.label {
+                asm.define(l, i64::from(asm.address));
+            }
             if let Some(i) = &line.instruction {
                 match i.mnemonic.as_str() {
                     "byte" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Address(p) => match p {
-                                    0..=255 => {
-                                        println!("# emitting raw byte {} at {}", p, address);
-                                        address += 1;
-                                        println!("echo -en '\\x{:02x}'", p);
-                                    }
-                                    _ => {
-                                        println!("invalid value for byte");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for byte");
-                                    panic!("unimplemented error handling")
-                                }
-                            }
-                        } else {
-                            println!("missing parameter for byte");
-                            panic!("unimplemented error handling")
+                        if asm.word_addressed {
+                            asm.error("byte not available on word-addressed processors");
                         }
-                    }
-                    "org" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Address(p) => match p {
-                                    0..=65535 => {
-                                        let newaddress = *p as u32;
-                                        if address == 0 {
-                                            println!("# setting origin to {}", newaddress);
-                                            address = newaddress;
-                                        } else if address < newaddress {
-                                            println!(
-                                                "# advancing to {} ({} bytes)",
-                                                p,
-                                                newaddress - address
-                                            );
-                                            println!("for i in {{{}..{}}}", address, newaddress - 1);
-                                            println!("do");
-                                            println!("  echo -en '\\x{:02x}'", 0xEA);
-                                            println!("done");
-                                            address = newaddress;
-                                        } else {
-                                            println!("attempt to move origin backward");
-                                            panic!("unimplemented error handling")
-                                        }
-                                    }
-                                    _ => {
-                                        println!("invalid address for org");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for org");
-                                    panic!("unimplemented error handling")
-                                }
-                            }
-                        } else {
-                            println!("missing parameter for org");
-                            panic!("unimplemented error handling")
+                        if i.parameters.is_empty() {
+                            asm.error("missing parameter for byte");
                         }
-                    }
-                    "processor" => {
-                        println!("# ignoring directive: {}", i.mnemonic);
-                    }
-                    "BCS" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Address(p) => match p {
-                                    0..=65535 => {
-                                        let destination = *p as u32;
-                                        if destination > address || destination < address - 128 {
-                                            println!("branch out of range for BCS");
-                                            panic!("unimplemented error handling")
-                                        }
-                                        println!("# emitting BCS opcode 0x{:02X} at {}", 0xB0, address);
-                                        address += 1;
-                                        println!(
-                                            "# emitting BCS parameter {} at {}",
-                                            destination + 256 - address,
-                                            address
-                                        );
-                                        address += 1;
-                                        println!(
-                                            "echo -en '\\x{:02x}\\x{:02x}'",
-                                            0xB0,
-                                            destination + 256 - address
-                                        );
-                                    }
-                                    _ => {
-                                        println!("invalid parameter value for BCS");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for BCS");
-                                    panic!("unimplemented error handling")
-                                }
+                        for p in &i.parameters {
+                            if let Expression::Immediate(_) = p {
+                                asm.error("wrong parameter type for byte");
                             }
-                        } else {
-                            println!("missing parameter for BCS");
-                            panic!("unimplemented error handling")
+                            let v = asm.value(p);
+                            asm.check((-128..=255).contains(&v), "invalid value for byte");
+                            asm.emit_byte((v & 255) as u8);
                         }
                     }
-                    "BIT" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Address(p) => match p {
-                                    0..=65535 => {
-                                        println!("# emitting BIT opcode 0x{:02X} at {}", 0x2C, address);
-                                        address += 1;
-                                        println!("# emitting BIT parameter {} at {}", p, address);
-                                        address += 2;
-                                        println!(
-                                            "echo -en '\\x{:02x}\\x{:02x}\\x{:02x}'",
-                                            0x2C,
-                                            p & 255,
-                                            p >> 8
-                                        );
-                                    }
-                                    _ => {
-                                        println!("invalid parameter value for BIT");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for BIT");
-                                    panic!("unimplemented error handling")
-                                }
-                            }
-                        } else {
-                            println!("missing parameter for BIT");
-                            panic!("unimplemented error handling")
+                    "org" => {
+                        let p = asm.parameter(i);
+                        if let Expression::Immediate(_) = p {
+                            asm.error("wrong parameter type for org");
                         }
-                    }
-                    "BPL" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Address(p) => match p {
-                                    0..=65535 => {
-                                        let destination = *p as u32;
-                                        if destination > address || destination < address - 128 {
-                                            println!("branch out of range for BPL");
-                                            panic!("unimplemented error handling")
-                                        }
-                                        println!("# emitting BPL opcode 0x{:02X} at {}", 0xD0, address);
-                                        address += 1;
-                                        println!(
-                                            "# emitting BPL parameter {} at {}",
-                                            destination + 256 - address,
-                                            address
-                                        );
-                                        address += 1;
-                                        println!(
-                                            "echo -en '\\x{:02x}\\x{:02x}'",
-                                            0xD0,
-                                            destination + 256 - address
-                                        );
-                                    }
-                                    _ => {
-                                        println!("invalid parameter value for BPL");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for BPL");
-                                    panic!("unimplemented error handling")
-                                }
-                            }
-                        } else {
-                            println!("missing parameter for BPL");
-                            panic!("unimplemented error handling")
+                        let v = asm.value(p);
+                        if !(0..=i64::from(backend.max_address())).contains(&v) {
+                            asm.error("invalid address for org");
                         }
+                        asm.org(v as u32, backend.fill());
                     }
-                    "CLC" => {
-                        if i.parameter.is_none() {
-                            println!("# emitting CLC opcode 0x{:02X} at {}", 0x18, address);
-                            address += 1;
-                            println!("echo -en '\\x{:02x}'", 0x18);
-                        } else {
-                            println!("unexpected parameter for CLC");
-                            panic!("unimplemented error handling")
+                    "rorg" => {
+                        let p = asm.parameter(i);
+                        if let Expression::Immediate(_) = p {
+                            asm.error("wrong parameter type for rorg");
                         }
-                    }
-                    "CLD" => {
-                        if i.parameter.is_none() {
-                            println!("# emitting CLD opcode 0x{:02X} at {}", 0xD8, address);
-                            address += 1;
-                            println!("echo -en '\\x{:02x}'", 0xD8);
-                        } else {
-                            println!("unexpected parameter for CLD");
-                            panic!("unimplemented error handling")
+                        let v = asm.value(p);
+                        if !(0..=i64::from(backend.max_address())).contains(&v) {
+                            asm.error("invalid address for rorg");
                         }
+                        asm.rorg(v as u32);
                     }
-                    "JMP" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Address(p) => match p {
-                                    0..=65535 => {
-                                        println!("# emitting JMP opcode 0x{:02X} at {}", 0x4C, address);
-                                        address += 1;
-                                        println!("# emitting JMP parameter {} at {}", p, address);
-                                        address += 2;
-                                        println!(
-                                            "echo -en '\\x{:02x}\\x{:02x}\\x{:02x}'",
-                                            0x4C,
-                                            p & 255,
-                                            p >> 8
-                                        );
-                                    }
-                                    _ => {
-                                        println!("invalid parameter value for JMP");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for JMP");
-                                    panic!("unimplemented error handling")
-                                }
-                            }
-                        } else {
-                            println!("missing parameter for JMP");
-                            panic!("unimplemented error handling")
-                        }
-                    }
-                    "LDA" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Immediate(p) => match p {
-                                    0..=255 => {
-                                        println!("# emitting LDA opcode 0x{:02X} at {}", 0xA9, address);
-                                        address += 1;
-                                        println!("# emitting LDA parameter {} at {}", p, address);
-                                        address += 1;
-                                        println!("echo -en '\\x{:02x}\\x{:02x}'", 0xA9, p);
-                                    }
-                                    _ => {
-                                        println!("invalid parameter value for LDA");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for LDA");
-                                    panic!("unimplemented error handling")
-                                }
-                            }
-                        } else {
-                            println!("missing parameter for LDA");
-                            panic!("unimplemented error handling")
+                    "entry" | "run" => {
+                        if asm.entry.is_some() {
+                            asm.error(&format!("duplicate {}", i.mnemonic));
                         }
+                        asm.entry = match asm.parameter(i) {
+                            Expression::Segment(s, o) => Some((Some(asm.value(s)), asm.value(o))),
+                            Expression::Immediate(_) => asm.error("wrong parameter type for entry"),
+                            e => Some((None, asm.value(e))),
+                        };
                     }
-                    "LDX" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Immediate(p) => match p {
-                                    0..=255 => {
-                                        println!("# emitting LDX opcode 0x{:02X} at {}", 0xA2, address);
-                                        address += 1;
-                                        println!("# emitting LDX parameter {} at {}", p, address);
-                                        address += 1;
-                                        println!("echo -en '\\x{:02x}\\x{:02x}'", 0xA2, p);
-                                    }
-                                    _ => {
-                                        println!("invalid parameter value for LDX");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for LDX");
-                                    panic!("unimplemented error handling")
-                                }
-                            }
-                        } else {
-                            println!("missing parameter for LDX");
-                            panic!("unimplemented error handling")
-                        }
+                    "init" => {
+                        let v = asm.value(asm.parameter(i));
+                        asm.inits.push((asm.image.len(), v));
                     }
-                    "RTI" => {
-                        if i.parameter.is_none() {
-                            println!("# emitting RTI opcode 0x{:02X} at {}", 0x40, address);
-                            address += 1;
-                            println!("echo -en '\\x{:02x}'", 0x40);
+                    "filetype" | "auxtype" => {
+                        let v = asm.value(asm.parameter(i));
+                        asm.check(
+                            (0..=65535).contains(&v),
+                            &format!("invalid parameter value for {}", i.mnemonic),
+                        );
+                        if i.mnemonic == "filetype" {
+                            asm.file_type = Some(v as u16);
                         } else {
-                            println!("unexpected parameter for RTI");
-                            panic!("unimplemented error handling")
+                            asm.aux_type = Some(v as u16);
                         }
                     }
-                    "SEI" => {
-                        if i.parameter.is_none() {
-                            println!("# emitting SEI opcode 0x{:02X} at {}", 0x78, address);
-                            address += 1;
-                            println!("echo -en '\\x{:02x}'", 0x78);
-                        } else {
-                            println!("unexpected parameter for SEI");
-                            panic!("unimplemented error handling")
+                    "processor" => {
+                        if final_pass {
+                            println!("# ignoring directive: {}", i.mnemonic);
                         }
                     }
-                    "STA" => {
-                        if let Some(p) = &i.parameter {
-                            match p {
-                                crate::Number::Address(p) => match p {
-                                    0..=65535 => {
-                                        println!("# emitting STA opcode 0x{:02X} at {}", 0x8D, address);
-                                        address += 1;
-                                        println!("# emitting STA parameter {} at {}", p, address);
-                                        address += 2;
-                                        println!(
-                                            "echo -en '\\x{:02x}\\x{:02x}\\x{:02x}'",
-                                            0x8D,
-                                            p & 255,
-                                            p >> 8
-                                        );
-                                    }
-                                    _ => {
-                                        println!("invalid parameter value for STA");
-                                        panic!("unimplemented error handling")
-                                    }
-                                },
-                                _ => {
-                                    println!("wrong parameter type for STA");
-                                    panic!("unimplemented error handling")
-                                }
-                            }
-                        } else {
-                            println!("missing parameter for STA");
-                            panic!("unimplemented error handling")
+                    _ => {
+                        if !backend.instruction(&mut asm, i) {
+                            asm.error(&format!("unknown instruction: {}", i.mnemonic));
                         }
                     }
-                    "TXS" => {
-                        if i.parameter.is_none() {
-                            println!("# emitting TXS opcode 0x{:02X} at {}", 0x9A, address);
-                            address += 1;
-                            println!("echo -en '\\x{:02x}'", 0x9A);
-                        } else {
-                            println!("unexpected parameter for TXS");
-                            panic!("unimplemented error handling")
-                        }
+                }
+            }
+        }
+        backend.end_pass(&mut asm);
+        asm
+    }
+}
+
+// Passes after which an assembly gives up on labels settling down
+const MAX_PASSES: u32 = 16;
+
+// A processor-specific back end
+//
+// Encodes the instructions that the generic directives don't handle,
+// returns false for mnemonics it doesn't know about.
+trait Backend {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool;
+
+    // Value used to fill memory when org skips ahead
+    fn fill(&self) -> u16;
+
+    // Whether each address holds a 16-bit word instead of a byte
+    fn word_addressed(&self) -> bool {
+        false
+    }
+
+    // Reset any state that the back end tracks from one instruction
+    // to the next
+    fn start_pass(&mut self) {}
+
+    // Symbols predefined for the target, like hardware registers
+    fn symbols(&self) -> &'static [(&'static str, i64)] {
+        &[]
+    }
+
+    // File header written before the code, from the last pass
+    fn header(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    // Finish anything left open at the end of the source
+    fn end_pass(&mut self, _asm: &mut Assembly) {}
+
+    // Whether addresses are made of a segment and an offset
+    fn segmented(&self) -> bool {
+        false
+    }
+
+    // Highest address that org and rorg accept
+    fn max_address(&self) -> u32 {
+        0xFFFF
+    }
+}
+
+// State of one assembly pass
+//
+// Symbols from the previous pass are used to resolve forward references.
+// Errors that depend on symbol values are only reported during the final
+// pass, when all the values are known.
+struct Assembly {
+    address: u32,
+    word_addressed: bool,
+    line: u32,
+    // Bank that the code is assembled in, on back ends with banks
+    bank: Option<i64>,
+    // Address of the first org, and bytes emitted in the final pass
+    origin: Option<u32>,
+    image: Vec<u8>,
+    // Blocks of the image: address, offset in the image, and whether
+    // it's padding
+    sections: Vec<(u32, usize, bool)>,
+    // Init addresses, with their offsets in the image
+    inits: Vec<(usize, i64)>,
+    // Start address, with its segment if any
+    entry: Option<(Option<i64>, i64)>,
+    // ProDOS file type and aux type
+    file_type: Option<u16>,
+    aux_type: Option<u16>,
+    // Test blocks, on processors that can be simulated
+    tests: Vec<Test>,
+    symbols: HashMap<String, i64>,
+    previous: HashMap<String, i64>,
+    changed: bool,
+    final_pass: bool,
+}
+
+impl Assembly {
+    fn define(&mut self, name: &str, value: i64) {
+        if self.symbols.contains_key(name) {
+            self.error(&format!("duplicate label: {}", name));
+        }
+        self.set(name, value);
+        // The bank of a label is kept under a name that can't be a label
+        if let Some(b) = self.bank {
+            self.set(&format!("bank({})", name), b);
+        }
+    }
+
+    fn set(&mut self, name: &str, value: i64) {
+        if self.previous.get(name) != Some(&value) {
+            self.changed = true;
+        }
+        self.symbols.insert(String::from(name), value);
+    }
+
+    // Look a symbol up, in this pass or in the previous one
+    fn symbol(&self, name: &str, message: &str) -> Option<i64> {
+        match self.symbols.get(name).or_else(|| self.previous.get(name)) {
+            Some(v) => Some(*v),
+            None => {
+                if self.final_pass {
+                    self.error(&format!("{}: {}", message, name));
+                }
+                None
+            }
+        }
+    }
+
+    // Compute the value of an expression
+    //
+    // Returns None for symbols that aren't known yet, which is an
+    // error in the final pass.
+    fn evaluate(&self, e: &Expression) -> Option<i64> {
+        match e {
+            Expression::Immediate(_) => self.error("unexpected immediate parameter"),
+            Expression::Indirect(_) | Expression::Deferred(_) => {
+                self.error("unexpected indirect parameter")
+            }
+            Expression::PostIncrement(_) | Expression::PostDecrement(_) => {
+                self.error("unexpected auto-increment or auto-decrement")
+            }
+            Expression::Indexed(_, _) => self.error("unexpected indexed parameter"),
+            Expression::Memory(_) => self.error("unexpected memory parameter"),
+            Expression::Segment(_, _) => self.error("unexpected segment"),
+            Expression::Qualified(q, _) => self.error(&format!("unexpected {}", q)),
+            Expression::Sum(a, b) => Some(self.evaluate(a)?.wrapping_add(self.evaluate(b)?)),
+            Expression::Difference(a, b) => Some(self.evaluate(a)?.wrapping_sub(self.evaluate(b)?)),
+            Expression::Product(a, b) => Some(self.evaluate(a)?.wrapping_mul(self.evaluate(b)?)),
+            Expression::Negation(e) => Some(self.evaluate(e)?.wrapping_neg()),
+            Expression::Parenthesized(l) => match l.as_slice() {
+                [e] => self.evaluate(e),
+                _ => self.error("unexpected list in expression"),
+            },
+            Expression::List(_) => self.error("unexpected list in expression"),
+            Expression::Text(_) => self.error("unexpected string"),
+            Expression::Assignment(_, _) => self.error("unexpected assignment"),
+            Expression::Range(_, _) => self.error("unexpected range"),
+            Expression::Identifier(s) => self.symbol(s, "undefined symbol"),
+            Expression::Bank(s) => self.symbol(&format!("bank({})", s), "no bank for symbol"),
+            Expression::Number(n) => Some(*n),
+        }
+    }
+
+    // Value of an expression, with unknown values counting as zero
+    // until the final pass
+    fn value(&self, e: &Expression) -> i64 {
+        self.evaluate(e).unwrap_or(0)
+    }
+
+    // The single parameter of an instruction
+    fn parameter<'i>(&self, i: &'i Instruction) -> &'i Expression {
+        match i.parameters.as_slice() {
+            [] => self.error(&format!("missing parameter for {}", i.mnemonic)),
+            [p] => p,
+            _ => self.error(&format!("too many parameters for {}", i.mnemonic)),
+        }
+    }
+
+    fn no_parameter(&self, i: &Instruction) {
+        if !i.parameters.is_empty() {
+            self.error(&format!("unexpected parameter for {}", i.mnemonic));
+        }
+    }
+
+    // Report a problem that doesn't prevent assembling, in the final pass,
+    // as a comment so that the output remains a valid script
+    fn warning(&self, message: &str) {
+        if self.final_pass {
+            println!("# warning: {} at line {}", message, self.line);
+        }
+    }
+
+    fn error(&self, message: &str) -> ! {
+        println!("{} at line {}", message, self.line);
+        panic!("unimplemented error handling")
+    }
+
+    // Report an error if a condition doesn't hold in the final pass
+    fn check(&self, condition: bool, message: &str) {
+        if self.final_pass && !condition {
+            self.error(message);
+        }
+    }
+
+    fn org(&mut self, address: u32, fill: u16) {
+        if self.address == 0 {
+            if self.final_pass {
+                println!("# setting origin to {}", address);
+            }
+            self.origin = Some(address);
+            self.address = address;
+            self.section(false);
+        } else {
+            self.pad(address, fill);
+        }
+    }
+
+    // Fill the output up to an address
+    fn pad(&mut self, address: u32, fill: u16) {
+        if self.address < address {
+            if self.final_pass {
+                println!(
+                    "# advancing to {} ({} {})",
+                    address,
+                    address - self.address,
+                    if self.word_addressed {
+                        "words"
+                    } else {
+                        "bytes"
                     }
-                    _ => {
-                        println!("unknown instruction: {}", i.mnemonic);
-                        panic!("unimplemented error handling")
+                );
+                println!("for i in {{{}..{}}}", self.address, address - 1);
+                println!("do");
+                if self.word_addressed {
+                    println!("  echo -en '\\x{:02x}\\x{:02x}'", fill >> 8, fill & 255);
+                } else {
+                    println!("  echo -en '\\x{:02x}'", fill);
+                }
+                println!("done");
+                self.section(true);
+                for _ in self.address..address {
+                    if self.word_addressed {
+                        self.image.extend_from_slice(&fill.to_be_bytes());
+                    } else {
+                        self.image.push(fill as u8);
                     }
                 }
+                self.address = address;
+                self.section(false);
             }
+        } else if self.address > address {
+            self.error("attempt to move origin backward");
         }
-        println!();
+        self.address = address;
+    }
+
+    // Change the address that the code is assembled for, without
+    // moving in the output
+    fn rorg(&mut self, address: u32) {
+        if self.final_pass {
+            println!("# relocating to {}", address);
+        }
+        self.address = address;
+        self.section(false);
+    }
+
+    // Start a new block of the image at the current address, in the
+    // final pass, replacing the previous block if it's empty
+    fn section(&mut self, fill: bool) {
+        if self.final_pass {
+            if self
+                .sections
+                .last()
+                .is_some_and(|s| s.1 == self.image.len())
+            {
+                self.sections.pop();
+            }
+            self.sections.push((self.address, self.image.len(), fill));
+        }
+    }
+
+    fn emit_byte(&mut self, b: u8) {
+        if self.final_pass {
+            println!("# emitting raw byte {} at {}", b, self.address);
+            println!("echo -en '\\x{:02x}'", b);
+            self.image.push(b);
+        }
+        self.address += 1;
+    }
+
+    // Emit an instruction, made of an opcode followed by
+    // the encoded parameter, if any
+    fn emit(&mut self, mnemonic: &str, bytes: &[u8], parameter: Option<i64>) {
+        if self.final_pass {
+            println!(
+                "# emitting {} opcode 0x{:02X} at {}",
+                mnemonic, bytes[0], self.address
+            );
+            if let Some(p) = parameter {
+                println!(
+                    "# emitting {} parameter {} at {}",
+                    mnemonic,
+                    p,
+                    self.address + 1
+                );
+            }
+            print!("echo -en '");
+            for b in bytes {
+                print!("\\x{:02x}", b);
+            }
+            println!("'");
+            self.image.extend_from_slice(bytes);
+        }
+        self.address += bytes.len() as u32;
+    }
+
+    // Number of addresses that a 16-bit word takes
+    fn word_size(&self) -> u32 {
+        if self.word_addressed {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Emit a raw 16-bit word, high byte first
+    fn emit_word(&mut self, w: u16) {
+        if self.final_pass {
+            println!("# emitting raw word {} at {}", w, self.address);
+            println!("echo -en '\\x{:02x}\\x{:02x}'", w >> 8, w & 255);
+            self.image.extend_from_slice(&w.to_be_bytes());
+        }
+        self.address += self.word_size();
+    }
+
+    // Emit an instruction made of 16-bit words, each written high
+    // byte first
+    fn emit_words(&mut self, mnemonic: &str, words: &[u16], parameter: Option<i64>) {
+        if self.final_pass {
+            println!(
+                "# emitting {} opcode 0x{:04X} at {}",
+                mnemonic, words[0], self.address
+            );
+            if let Some(p) = parameter {
+                println!(
+                    "# emitting {} parameter {} at {}",
+                    mnemonic,
+                    p,
+                    self.address + self.word_size()
+                );
+            }
+            print!("echo -en '");
+            for w in words {
+                print!("\\x{:02x}\\x{:02x}", w >> 8, w & 255);
+                self.image.extend_from_slice(&w.to_be_bytes());
+            }
+            println!("'");
+        }
+        self.address += words.len() as u32 * self.word_size();
     }
 }
 
@@ -484,14 +944,14 @@ struct SourceFile<'lt> {
 }
 
 impl SourceFile<'_> {
-    fn new(s: &str) -> SourceFile {
+    fn new<'a>(s: &'a str, file: &str) -> SourceFile<'a> {
         let mut iter = s.chars();
         SourceFile::<'_> {
             current: iter.next(),
             future: iter,
             line: 1,
             column: 1,
-            file: String::from("<builtin>"),
+            file: String::from(file),
         }
     }
 
@@ -543,6 +1003,11 @@ enum InstructionLexerState {
     BeforeInstruction,
     InInstruction,
 }
+
+enum IdentifierLexerState {
+    BeforeIdentifier,
+    InIdentifier,
+}
 /*
 enum ParameterLexerState {
     BeforeParameter,
@@ -552,6 +1017,8 @@ enum ParameterLexerState {
 enum NumberLexerState {
     BeforeNumber,
     InNumber,
+    BeforeHexDigits,
+    InHexDigits,
 }
 
 struct Parser<'lt> {
@@ -559,30 +1026,26 @@ struct Parser<'lt> {
 }
 
 impl Parser<'_> {
-    fn new(s: &str) -> Parser {
-        return Parser {
-            src: SourceFile::new(s),
-        };
+    fn new<'a>(s: &'a str, file: &str) -> Parser<'a> {
+        Parser {
+            src: SourceFile::new(s, file),
+        }
     }
 
     // Parse an entire source file
     //
     // A source file is made of lines, parse lines one at a time
     fn parse_source(&mut self) -> ParsedSource {
-        let mut ret = ParsedSource { lines: Vec::new(), };
+        let mut ret = ParsedSource { lines: Vec::new() };
         while !self.src.is_eof() {
             let l = self.parse_line();
-            if l.label.is_some() {
-                println!("final label: {}", l.label.as_ref().unwrap());
+            if let Some(label) = &l.label {
+                println!("final label: {}", label);
             }
-            if l.instruction.is_some() {
-                let i = l.instruction.as_ref().unwrap();
+            if let Some(i) = &l.instruction {
                 println!("final menmonic: {}", i.mnemonic);
-                if i.parameter.is_some() {
-                    match i.parameter.as_ref().unwrap() {
-                        crate::Number::Immediate(p) => println!("final immediate: {}", p),
-                        crate::Number::Address(p) => println!("final address: {}", p),
-                    }
+                for p in &i.parameters {
+                    println!("final parameter: {}", p);
                 }
             }
             ret.lines.push(l);
@@ -599,12 +1062,13 @@ impl Parser<'_> {
     fn parse_line(&mut self) -> ParsedLine {
         println!("parse_line");
         let mut ret = ParsedLine {
+            line: self.src.line,
             label: None,
             instruction: None,
         };
         ret.label = self.lex_label();
-        if ret.label.is_some() {
-            println!("found label: {}", ret.label.as_ref().unwrap());
+        if let Some(label) = &ret.label {
+            println!("found label: {}", label);
             self.skip_optional_space();
             ret.instruction = self.parse_after_label();
             return ret;
@@ -637,20 +1101,37 @@ impl Parser<'_> {
         if let Some(i) = inst {
             let mut ret = Instruction {
                 mnemonic: i,
-                parameter: None,
+                parameters: Vec::new(),
             };
             println!("found instruction: {}", ret.mnemonic);
             if !self.skip_space() {
                 return Some(ret);
             }
-            ret.parameter = self.parse_parameters();
+            if ret.mnemonic == "processor" || ret.mnemonic == "bankswitch" {
+                // Processor names like 65c02 and bank switching schemes
+                // like 3F aren't valid identifiers
+                if let Some(name) = self.lex_name() {
+                    ret.parameters.push(Expression::Identifier(name));
+                }
+                return Some(ret);
+            }
+            if ret.mnemonic == "set" || ret.mnemonic == "expect" {
+                ret.parameters = self.parse_assignments();
+                return Some(ret);
+            }
+            ret.parameters = self.parse_parameters();
             return Some(ret);
         }
         None
     }
 
-    fn parse_parameters(&mut self) -> Option<Number> {
+    // Parse the parameters of an instruction, if any
+    //
+    // expression_list : expression
+    //                 | expression ',' expression_list
+    fn parse_parameters(&mut self) -> Vec<Expression> {
         println!("parse_parameters");
+        let mut ret = Vec::new();
         match self.src.peek() {
             None => {
                 print!("unexpected end of file at ");
@@ -658,27 +1139,304 @@ impl Parser<'_> {
                 println!();
                 panic!("unimplemented error handling");
             }
-            Some(c) => match c {
-                '#' => {
-                    self.src.advance();
-                    self.skip_optional_space();
-                    match self.lex_number() {
-                        None => None,
-                        Some(n) => {
-                            println!("found immediate: {}", n);
-                            Some(Number::Immediate(n))
-                        }
-                    }
+            Some(';') | Some('\n') => return ret,
+            Some(_) => (),
+        }
+        loop {
+            let e = self.parse_expression();
+            println!("found parameter: {}", e);
+            ret.push(e);
+            self.skip_optional_space();
+            if self.src.peek() != Some(',') {
+                return ret;
+            }
+            self.src.advance();
+            self.skip_optional_space();
+        }
+    }
+
+    // Parse the parameters of set and expect
+    //
+    // assignment_list : assignment
+    //                 | assignment ',' assignment_list
+    // assignment : target '=' sum
+    //            | target '=' '[' expression_list ']'
+    // target : IDENTIFIER
+    //        | IDENTIFIER SPACE IDENTIFIER
+    //        | IDENTIFIER '[' sum ']'
+    //        | IDENTIFIER '[' sum '..' sum ']'
+    fn parse_assignments(&mut self) -> Vec<Expression> {
+        println!("parse_assignments");
+        let mut ret = Vec::new();
+        loop {
+            let target = self.parse_target();
+            self.skip_optional_space();
+            self.require('=');
+            self.skip_optional_space();
+            let value = if self.src.peek() == Some('[') {
+                self.src.advance();
+                self.skip_optional_space();
+                let l = self.parse_parameters();
+                self.require(']');
+                Expression::List(l)
+            } else {
+                self.parse_sum()
+            };
+            let e = Expression::Assignment(Box::new(target), Box::new(value));
+            println!("found parameter: {}", e);
+            ret.push(e);
+            self.skip_optional_space();
+            if self.src.peek() != Some(',') {
+                return ret;
+            }
+            self.src.advance();
+            self.skip_optional_space();
+        }
+    }
+
+    // Parse a register, a flag or memory that set and expect assign
+    fn parse_target(&mut self) -> Expression {
+        println!("parse_target");
+        let name = match self.lex_identifier() {
+            Some(n) => n,
+            None => {
+                print!("expected register, flag or memory at ");
+                self.src.print_location();
+                println!();
+                panic!("unimplemented error handling");
+            }
+        };
+        if self.src.peek() == Some('[') {
+            self.src.advance();
+            self.skip_optional_space();
+            let mut e = self.parse_sum();
+            if self.src.peek() == Some('.') {
+                self.src.advance();
+                self.require('.');
+                self.skip_optional_space();
+                e = Expression::Range(Box::new(e), Box::new(self.parse_sum()));
+            }
+            self.require(']');
+            return Expression::Qualified(name, Box::new(Expression::Memory(Box::new(e))));
+        }
+        if self.skip_space() {
+            if let Some(f) = self.lex_identifier() {
+                return Expression::Qualified(name, Box::new(Expression::Identifier(f)));
+            }
+        }
+        Expression::Identifier(name)
+    }
+
+    // Skip a character that the syntax requires
+    fn require(&mut self, c: char) {
+        if self.src.peek() != Some(c) {
+            print!("expected '{}' at ", c);
+            self.src.print_location();
+            println!();
+            panic!("unimplemented error handling");
+        }
+        self.src.advance();
+    }
+
+    // Parse an expression
+    //
+    // expression : '#' sum
+    //            | '@' sum
+    //            | '@' sum '(' expression ')'
+    //            | '*' sum
+    //            | sum ':' sum
+    //            | sum
+    fn parse_expression(&mut self) -> Expression {
+        println!("parse_expression");
+        match self.src.peek() {
+            Some('#') => {
+                self.src.advance();
+                self.skip_optional_space();
+                Expression::Immediate(Box::new(self.parse_sum()))
+            }
+            Some('@') => {
+                self.src.advance();
+                self.skip_optional_space();
+                let e = self.parse_sum();
+                if self.src.peek() != Some('(') {
+                    return Expression::Indirect(Box::new(e));
+                }
+                self.src.advance();
+                self.skip_optional_space();
+                let r = self.parse_expression();
+                self.skip_optional_space();
+                if self.src.peek() != Some(')') {
+                    print!("expected ')' at ");
+                    self.src.print_location();
+                    println!();
+                    panic!("unimplemented error handling");
+                }
+                self.src.advance();
+                Expression::Indirect(Box::new(Expression::Indexed(Box::new(e), Box::new(r))))
+            }
+            Some('*') => {
+                self.src.advance();
+                self.skip_optional_space();
+                Expression::Deferred(Box::new(self.parse_sum()))
+            }
+            _ => {
+                let e = self.parse_sum();
+                self.skip_optional_space();
+                if self.src.peek() != Some(':') {
+                    return e;
+                }
+                self.src.advance();
+                self.skip_optional_space();
+                Expression::Segment(Box::new(e), Box::new(self.parse_sum()))
+            }
+        }
+    }
+
+    // Parse a sum
+    //
+    // sum : product
+    //     | sum '+' product
+    //     | sum '-' product
+    //     | sum '+'
+    //     | sum '-'
+    //
+    // A trailing '+' or '-' is an auto-increment or auto-decrement
+    fn parse_sum(&mut self) -> Expression {
+        println!("parse_sum");
+        let mut ret = self.parse_product();
+        loop {
+            self.skip_optional_space();
+            let op = match self.src.peek() {
+                Some(c) if c == '+' || c == '-' => c,
+                _ => return ret,
+            };
+            self.src.advance();
+            self.skip_optional_space();
+            if let Some(',') | Some(')') | Some(';') | Some('\n') = self.src.peek() {
+                if op == '+' {
+                    return Expression::PostIncrement(Box::new(ret));
                 }
-                _ => match self.lex_number() {
-                    None => None,
-                    Some(n) => {
-                        println!("found address: {}", n);
-                        Some(Number::Address(n))
+                return Expression::PostDecrement(Box::new(ret));
+            }
+            let rhs = Box::new(self.parse_product());
+            if op == '+' {
+                ret = Expression::Sum(Box::new(ret), rhs);
+            } else {
+                ret = Expression::Difference(Box::new(ret), rhs);
+            }
+        }
+    }
+
+    // Parse a product
+    //
+    // product : operand
+    //         | product '*' operand
+    fn parse_product(&mut self) -> Expression {
+        println!("parse_product");
+        let mut ret = self.parse_operand();
+        loop {
+            self.skip_optional_space();
+            if self.src.peek() != Some('*') {
+                return ret;
+            }
+            self.src.advance();
+            self.skip_optional_space();
+            ret = Expression::Product(Box::new(ret), Box::new(self.parse_operand()));
+        }
+    }
+
+    // Parse an operand
+    //
+    // operand : IDENTIFIER
+    //         | IDENTIFIER SPACE expression
+    //         | NUMBER
+    //         | '-' operand
+    //         | '(' expression_list ')'
+    //         | '[' expression ']'
+    //         | STRING
+    fn parse_operand(&mut self) -> Expression {
+        println!("parse_operand");
+        if self.src.peek() == Some('"') {
+            self.src.advance();
+            let mut s = String::new();
+            loop {
+                match self.src.peek() {
+                    Some('"') => break,
+                    Some('\n') | None => {
+                        print!("unterminated string at ");
+                        self.src.print_location();
+                        println!();
+                        panic!("unimplemented error handling");
                     }
-                },
-            },
+                    Some(c) => s.push(c),
+                }
+                self.src.advance();
+            }
+            self.src.advance();
+            return Expression::Text(s);
         }
+        if self.src.peek() == Some('-') {
+            self.src.advance();
+            self.skip_optional_space();
+            return Expression::Negation(Box::new(self.parse_operand()));
+        }
+        if self.src.peek() == Some('(') {
+            self.src.advance();
+            self.skip_optional_space();
+            let l = self.parse_parameters();
+            if self.src.peek() != Some(')') {
+                print!("expected ')' at ");
+                self.src.print_location();
+                println!();
+                panic!("unimplemented error handling");
+            }
+            self.src.advance();
+            return Expression::Parenthesized(l);
+        }
+        if self.src.peek() == Some('[') {
+            self.src.advance();
+            self.skip_optional_space();
+            let e = self.parse_expression();
+            self.skip_optional_space();
+            if self.src.peek() != Some(']') {
+                print!("expected ']' at ");
+                self.src.print_location();
+                println!();
+                panic!("unimplemented error handling");
+            }
+            self.src.advance();
+            return Expression::Memory(Box::new(e));
+        }
+        if let Some(n) = self.lex_number() {
+            return Expression::Number(n);
+        }
+        if let Some(s) = self.lex_identifier() {
+            if s == "bank" && self.src.peek() == Some('(') {
+                self.src.advance();
+                self.skip_optional_space();
+                let label = self.lex_identifier();
+                self.skip_optional_space();
+                if label.is_none() || self.src.peek() != Some(')') {
+                    print!("expected label in bank() at ");
+                    self.src.print_location();
+                    println!();
+                    panic!("unimplemented error handling");
+                }
+                self.src.advance();
+                return Expression::Bank(label.unwrap());
+            }
+            // A name followed by another operand qualifies it
+            if self.skip_space() {
+                if let Some('a'..='z' | 'A'..='Z' | '_' | '0'..='9' | '$' | '[') = self.src.peek() {
+                    return Expression::Qualified(s, Box::new(self.parse_expression()));
+                }
+            }
+            return Expression::Identifier(s);
+        }
+        print!("expected operand at ");
+        self.src.print_location();
+        println!();
+        panic!("unimplemented error handling");
     }
 
     fn lex_label(&mut self) -> Option<String> {
@@ -698,7 +1456,7 @@ impl Parser<'_> {
                 BeforeLabel => match self.src.peek() {
                     None => return None,
                     Some(c) => match c {
-                        'a'..='z' | 'A'..='Z' => {
+                        'a'..='z' | 'A'..='Z' | '_' => {
                             ret.push(c);
                             self.src.advance();
                             state = InLabel;
@@ -714,7 +1472,7 @@ impl Parser<'_> {
                         panic!("unimplemented error handling");
                     }
                     Some(c) => match c {
-                        'a'..='z' | 'A'..='Z' => {
+                        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
                             ret.push(c);
                             self.src.advance();
                         }
@@ -767,7 +1525,10 @@ impl Parser<'_> {
                         panic!("unimplemented error handling");
                     }
                     Some(c) => match c {
-                        'a'..='z' | 'A'..='Z' | '0'..='9' => {
+                        // '@' ends CP1610 indirect mnemonics, ','
+                        // introduces the register field on the 2650,
+                        // '.' separates groups of directives like ines.prg
+                        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '@' | ',' | '.' => {
                             ret.push(c);
                             self.src.advance();
                         }
@@ -780,6 +1541,76 @@ impl Parser<'_> {
         }
     }
 
+    // Lex an identifier, i.e. a symbol or a register name
+    fn lex_identifier(&mut self) -> Option<String> {
+        use crate::IdentifierLexerState::*;
+
+        let mut state = BeforeIdentifier;
+        let mut ret = String::from("");
+        loop {
+            print!("lex_identifier loop, state: ");
+            match state {
+                BeforeIdentifier => print!("before identifier, "),
+                InIdentifier => print!("in identifier, "),
+            }
+            self.src.print_current();
+            println!();
+            match state {
+                BeforeIdentifier => match self.src.peek() {
+                    None => return None,
+                    Some(c) => match c {
+                        'a'..='z' | 'A'..='Z' | '_' => {
+                            ret.push(c);
+                            self.src.advance();
+                            state = InIdentifier;
+                        }
+                        _ => return None,
+                    },
+                },
+                InIdentifier => match self.src.peek() {
+                    None => {
+                        print!("unexpected end of file at ");
+                        self.src.print_location();
+                        println!();
+                        panic!("unimplemented error handling");
+                    }
+                    Some(c) => match c {
+                        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
+                            ret.push(c);
+                            self.src.advance();
+                        }
+                        _ => {
+                            return Some(ret);
+                        }
+                    },
+                },
+            }
+        }
+    }
+
+    // Lex a name made of letters and digits in any order,
+    // e.g. the name of a processor
+    fn lex_name(&mut self) -> Option<String> {
+        let mut ret = String::from("");
+        loop {
+            print!("lex_name loop, ");
+            self.src.print_current();
+            println!();
+            match self.src.peek() {
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    ret.push(c);
+                    self.src.advance();
+                }
+                _ => break,
+            }
+        }
+        if ret.is_empty() {
+            None
+        } else {
+            Some(ret)
+        }
+    }
+
     /*
         fn lex_parameter(&mut self) -> Option<String> {
             use crate::ParameterLexerState::*;
@@ -827,7 +1658,7 @@ impl Parser<'_> {
             }
         }
     */
-    // Lex a number
+    // Lex a number, either decimal or hexadecimal with a '$' prefix
     fn lex_number(&mut self) -> Option<i64> {
         use crate::NumberLexerState::*;
 
@@ -838,6 +1669,8 @@ impl Parser<'_> {
             match state {
                 BeforeNumber => print!("before number, "),
                 InNumber => print!("in number, "),
+                BeforeHexDigits => print!("before hex number, "),
+                InHexDigits => print!("in hex number, "),
             }
             self.src.print_current();
             println!();
@@ -850,6 +1683,10 @@ impl Parser<'_> {
                             ret = ret * 10 + i64::from(c.to_digit(10).unwrap());
                             state = InNumber;
                         }
+                        '$' => {
+                            self.src.advance();
+                            state = BeforeHexDigits;
+                        }
                         _ => return None,
                     },
                 },
@@ -870,6 +1707,36 @@ impl Parser<'_> {
                         }
                     },
                 },
+                BeforeHexDigits => match self.src.peek() {
+                    Some(c) if c.is_ascii_hexdigit() => {
+                        self.src.advance();
+                        ret = i64::from(c.to_digit(16).unwrap());
+                        state = InHexDigits;
+                    }
+                    _ => {
+                        print!("invalid hex number at ");
+                        self.src.print_location();
+                        println!();
+                        panic!("unimplemented error handling")
+                    }
+                },
+                InHexDigits => match self.src.peek() {
+                    None => {
+                        print!("unexpected end of file at ");
+                        self.src.print_location();
+                        println!();
+                        panic!("unimplemented error handling")
+                    }
+                    Some(c) => match c {
+                        '0'..='9' | 'a'..='f' | 'A'..='F' => {
+                            self.src.advance();
+                            ret = ret * 16 + i64::from(c.to_digit(16).unwrap());
+                        }
+                        _ => {
+                            return Some(ret);
+                        }
+                    },
+                },
             }
         }
     }
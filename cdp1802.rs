@@ -0,0 +1,234 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// RCA CDP1802 (COSMAC) back end, as used in the RCA Studio II
+// and the COSMAC VIP
+//
+// Most instructions are a single byte whose low nibble selects one of
+// the 16 scratchpad registers. Short branches only replace the low byte
+// of the program counter, so they can't leave the current 256-byte page,
+// while long branches carry a full 16-bit address.
+
+use crate::{Assembly, Backend, Expression, Instruction};
+
+pub struct Cdp1802Assembler {}
+
+// How the low bits of the opcode and the following bytes are encoded
+enum Form {
+    Implied,
+    Register,
+    // LDN with register 0 would be IDL
+    RegisterNotZero,
+    Port,
+    Immediate,
+    ShortBranch,
+    LongBranch,
+}
+
+impl Backend for Cdp1802Assembler {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool {
+        use Form::*;
+
+        let (opcode, form) = match i.mnemonic.as_str() {
+            "IDL" => (0x00, Implied),
+            "LDN" => (0x00, RegisterNotZero),
+            "INC" => (0x10, Register),
+            "DEC" => (0x20, Register),
+            "BR" => (0x30, ShortBranch),
+            "BQ" => (0x31, ShortBranch),
+            "BZ" => (0x32, ShortBranch),
+            "BDF" | "BPZ" | "BGE" => (0x33, ShortBranch),
+            "B1" => (0x34, ShortBranch),
+            "B2" => (0x35, ShortBranch),
+            "B3" => (0x36, ShortBranch),
+            "B4" => (0x37, ShortBranch),
+            "SKP" | "NBR" => (0x38, Implied),
+            "BNQ" => (0x39, ShortBranch),
+            "BNZ" => (0x3A, ShortBranch),
+            "BNF" | "BM" | "BL" => (0x3B, ShortBranch),
+            "BN1" => (0x3C, ShortBranch),
+            "BN2" => (0x3D, ShortBranch),
+            "BN3" => (0x3E, ShortBranch),
+            "BN4" => (0x3F, ShortBranch),
+            "LDA" => (0x40, Register),
+            "STR" => (0x50, Register),
+            "IRX" => (0x60, Implied),
+            "OUT" => (0x60, Port),
+            "INP" => (0x68, Port),
+            "RET" => (0x70, Implied),
+            "DIS" => (0x71, Implied),
+            "LDXA" => (0x72, Implied),
+            "STXD" => (0x73, Implied),
+            "ADC" => (0x74, Implied),
+            "SDB" => (0x75, Implied),
+            "SHRC" | "RSHR" => (0x76, Implied),
+            "SMB" => (0x77, Implied),
+            "SAV" => (0x78, Implied),
+            "MARK" => (0x79, Implied),
+            "REQ" => (0x7A, Implied),
+            "SEQ" => (0x7B, Implied),
+            "ADCI" => (0x7C, Immediate),
+            "SDBI" => (0x7D, Immediate),
+            "SHLC" | "RSHL" => (0x7E, Implied),
+            "SMBI" => (0x7F, Immediate),
+            "GLO" => (0x80, Register),
+            "GHI" => (0x90, Register),
+            "PLO" => (0xA0, Register),
+            "PHI" => (0xB0, Register),
+            "LBR" => (0xC0, LongBranch),
+            "LBQ" => (0xC1, LongBranch),
+            "LBZ" => (0xC2, LongBranch),
+            "LBDF" => (0xC3, LongBranch),
+            "NOP" => (0xC4, Implied),
+            "LSNQ" => (0xC5, Implied),
+            "LSNZ" => (0xC6, Implied),
+            "LSNF" => (0xC7, Implied),
+            "LSKP" | "NLBR" => (0xC8, Implied),
+            "LBNQ" => (0xC9, LongBranch),
+            "LBNZ" => (0xCA, LongBranch),
+            "LBNF" => (0xCB, LongBranch),
+            "LSIE" => (0xCC, Implied),
+            "LSQ" => (0xCD, Implied),
+            "LSZ" => (0xCE, Implied),
+            "LSDF" => (0xCF, Implied),
+            "SEP" => (0xD0, Register),
+            "SEX" => (0xE0, Register),
+            "LDX" => (0xF0, Implied),
+            "OR" => (0xF1, Implied),
+            "AND" => (0xF2, Implied),
+            "XOR" => (0xF3, Implied),
+            "ADD" => (0xF4, Implied),
+            "SD" => (0xF5, Implied),
+            "SHR" => (0xF6, Implied),
+            "SM" => (0xF7, Implied),
+            "LDI" => (0xF8, Immediate),
+            "ORI" => (0xF9, Immediate),
+            "ANI" => (0xFA, Immediate),
+            "XRI" => (0xFB, Immediate),
+            "ADI" => (0xFC, Immediate),
+            "SDI" => (0xFD, Immediate),
+            "SHL" => (0xFE, Implied),
+            "SMI" => (0xFF, Immediate),
+            _ => return false,
+        };
+        match form {
+            Implied => {
+                asm.no_parameter(i);
+                asm.emit(&i.mnemonic, &[opcode], None);
+            }
+            Register | RegisterNotZero => {
+                let r = self.register(asm, i);
+                if let RegisterNotZero = form {
+                    asm.check(
+                        r != 0,
+                        &format!("register 0 not allowed for {}", i.mnemonic),
+                    );
+                }
+                asm.emit(&i.mnemonic, &[opcode | r], None);
+            }
+            Port => {
+                let p = asm.parameter(i);
+                if let Expression::Immediate(_) = p {
+                    asm.error(&format!("wrong parameter type for {}", i.mnemonic));
+                }
+                let p = asm.value(p);
+                asm.check(
+                    (1..=7).contains(&p),
+                    &format!("invalid port for {}", i.mnemonic),
+                );
+                asm.emit(&i.mnemonic, &[opcode | (p & 7) as u8], None);
+            }
+            Immediate => {
+                // The immediate marker is optional, the operand is always a byte
+                let p = match asm.parameter(i) {
+                    Expression::Immediate(p) => asm.value(p),
+                    p => asm.value(p),
+                };
+                asm.check(
+                    (-128..=255).contains(&p),
+                    &format!("invalid parameter value for {}", i.mnemonic),
+                );
+                asm.emit(&i.mnemonic, &[opcode, (p & 255) as u8], Some(p));
+            }
+            ShortBranch => {
+                let destination = self.address(asm, i);
+                // The branch stays within the page of its operand byte
+                let page = (asm.address + 1) >> 8;
+                asm.check(
+                    destination >> 8 == page,
+                    &format!(
+                        "short branch {} from {} to {} crosses a page boundary",
+                        i.mnemonic, asm.address, destination
+                    ),
+                );
+                asm.emit(
+                    &i.mnemonic,
+                    &[opcode, (destination & 255) as u8],
+                    Some(i64::from(destination)),
+                );
+            }
+            LongBranch => {
+                let destination = self.address(asm, i);
+                asm.emit(
+                    &i.mnemonic,
+                    &[opcode, (destination >> 8) as u8, (destination & 255) as u8],
+                    Some(i64::from(destination)),
+                );
+            }
+        }
+        true
+    }
+
+    fn fill(&self) -> u16 {
+        0xC4
+    }
+}
+
+impl Cdp1802Assembler {
+    // Register number, either as R0-RF or as a plain expression
+    fn register(&self, asm: &Assembly, i: &Instruction) -> u8 {
+        let p = asm.parameter(i);
+        if let Expression::Identifier(s) = p {
+            if let Some(r) = s.strip_prefix('R') {
+                if r.len() == 1 {
+                    if let Some(r) = r.chars().next().unwrap().to_digit(16) {
+                        return r as u8;
+                    }
+                }
+            }
+        }
+        if let Expression::Immediate(_) = p {
+            asm.error(&format!("wrong parameter type for {}", i.mnemonic));
+        }
+        let r = asm.value(p);
+        asm.check(
+            (0..=15).contains(&r),
+            &format!("invalid register for {}", i.mnemonic),
+        );
+        (r & 15) as u8
+    }
+
+    fn address(&self, asm: &Assembly, i: &Instruction) -> u32 {
+        let p = asm.parameter(i);
+        if let Expression::Immediate(_) = p {
+            asm.error(&format!("wrong parameter type for {}", i.mnemonic));
+        }
+        let p = asm.value(p);
+        asm.check(
+            (0..=65535).contains(&p),
+            &format!("invalid address for {}", i.mnemonic),
+        );
+        (p & 65535) as u32
+    }
+}
@@ -0,0 +1,424 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// General Instrument CP1610 back end, as used in the Intellivision
+//
+// Memory is addressed in 16-bit words. Opcodes only use 10 bits, which
+// is all the original cartridge ROMs stored ("decles"), so immediate
+// values and addresses that follow an opcode must fit in the ROM width
+// unless an SDBD prefix splits them across two words, low byte first.
+// Operands are written source first, destination last.
+
+use crate::{Assembly, Backend, Expression, Instruction};
+
+pub struct Cp1610Assembler {
+    // Width of the ROM words, 10 or 16 bits
+    romw: u32,
+    // Address after the previous instruction if it was SDBD
+    sdbd: Option<u32>,
+}
+
+impl Cp1610Assembler {
+    pub fn new() -> Cp1610Assembler {
+        Cp1610Assembler {
+            romw: 16,
+            sdbd: None,
+        }
+    }
+}
+
+enum Form {
+    Implied,
+    // Single register, with the highest register allowed
+    Register(u16),
+    // Register in both the source and destination fields
+    SameRegister,
+    // Register at a given bit position, the other field being implied
+    RegisterField(u16),
+    RegisterRegister,
+    Shift,
+    Branch,
+    // Jumps and jumps to subroutine, with the interrupt enable flags
+    Jump(u16),
+    JumpSubroutine(u16),
+    Direct,
+    Indirect,
+    Immediate,
+    StoreDirect,
+    StoreIndirect,
+}
+
+impl Backend for Cp1610Assembler {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool {
+        use Form::*;
+
+        // SDBD only makes sense right in front of an instruction that
+        // reads a value from memory, so nothing can be emitted between
+        // them and a directive can't follow it
+        let sdbd = self.sdbd.take();
+        if sdbd.is_some_and(|a| a != asm.address) {
+            asm.error(&format!("code between SDBD and {}", i.mnemonic));
+        }
+        if sdbd.is_some() && ["romw", "decle", "bidecle"].contains(&i.mnemonic.as_str()) {
+            asm.error(&format!(
+                "SDBD must be followed by an immediate or indirect instruction, not {}",
+                i.mnemonic
+            ));
+        }
+
+        let (opcode, form) = match i.mnemonic.as_str() {
+            "romw" => {
+                self.romw(asm, i);
+                return true;
+            }
+            "decle" => {
+                self.decle(asm, i);
+                return true;
+            }
+            "bidecle" => {
+                self.bidecle(asm, i);
+                return true;
+            }
+            "HLT" => (0x000, Implied),
+            "SDBD" => (0x001, Implied),
+            "EIS" => (0x002, Implied),
+            "DIS" => (0x003, Implied),
+            "TCI" => (0x005, Implied),
+            "CLRC" => (0x006, Implied),
+            "SETC" => (0x007, Implied),
+            "INCR" => (0x008, Register(7)),
+            "DECR" => (0x010, Register(7)),
+            "COMR" => (0x018, Register(7)),
+            "NEGR" => (0x020, Register(7)),
+            "ADCR" => (0x028, Register(7)),
+            "GSWD" => (0x030, Register(3)),
+            "NOP" => (0x034, Implied),
+            "SIN" => (0x036, Implied),
+            "RSWD" => (0x038, Register(7)),
+            "SWAP" => (0x040, Shift),
+            "SLL" => (0x048, Shift),
+            "RLC" => (0x050, Shift),
+            "SLLC" => (0x058, Shift),
+            "SLR" => (0x060, Shift),
+            "SAR" => (0x068, Shift),
+            "RRC" => (0x070, Shift),
+            "SARC" => (0x078, Shift),
+            "MOVR" => (0x080, RegisterRegister),
+            "TSTR" => (0x080, SameRegister),
+            "JR" => (0x087, RegisterField(3)),
+            "ADDR" => (0x0C0, RegisterRegister),
+            "SUBR" => (0x100, RegisterRegister),
+            "CMPR" => (0x140, RegisterRegister),
+            "ANDR" => (0x180, RegisterRegister),
+            "XORR" => (0x1C0, RegisterRegister),
+            "CLRR" => (0x1C0, SameRegister),
+            "B" => (0x200, Branch),
+            "BC" | "BLGT" => (0x201, Branch),
+            "BOV" => (0x202, Branch),
+            "BPL" => (0x203, Branch),
+            "BEQ" | "BZE" => (0x204, Branch),
+            "BLT" => (0x205, Branch),
+            "BLE" => (0x206, Branch),
+            "BUSC" => (0x207, Branch),
+            "NOPP" => (0x208, Branch),
+            "BNC" | "BLLT" => (0x209, Branch),
+            "BNOV" => (0x20A, Branch),
+            "BMI" => (0x20B, Branch),
+            "BNEQ" | "BNZE" => (0x20C, Branch),
+            "BGE" => (0x20D, Branch),
+            "BGT" => (0x20E, Branch),
+            "BESC" => (0x20F, Branch),
+            "J" => (0x004, Jump(0)),
+            "JE" => (0x004, Jump(1)),
+            "JD" => (0x004, Jump(2)),
+            "JSR" => (0x004, JumpSubroutine(0)),
+            "JSRE" => (0x004, JumpSubroutine(1)),
+            "JSRD" => (0x004, JumpSubroutine(2)),
+            "MVO" => (0x240, StoreDirect),
+            "MVO@" => (0x240, StoreIndirect),
+            "PSHR" => (0x270, RegisterField(0)),
+            "MVI" => (0x280, Direct),
+            "MVI@" => (0x280, Indirect),
+            "MVII" => (0x280, Immediate),
+            "PULR" => (0x2B0, Register(7)),
+            "ADD" => (0x2C0, Direct),
+            "ADD@" => (0x2C0, Indirect),
+            "ADDI" => (0x2C0, Immediate),
+            "SUB" => (0x300, Direct),
+            "SUB@" => (0x300, Indirect),
+            "SUBI" => (0x300, Immediate),
+            "CMP" => (0x340, Direct),
+            "CMP@" => (0x340, Indirect),
+            "CMPI" => (0x340, Immediate),
+            "AND" => (0x380, Direct),
+            "AND@" => (0x380, Indirect),
+            "ANDI" => (0x380, Immediate),
+            "XOR" => (0x3C0, Direct),
+            "XOR@" => (0x3C0, Indirect),
+            "XORI" => (0x3C0, Immediate),
+            _ => return false,
+        };
+
+        let sdbd = sdbd.is_some();
+        if i.mnemonic == "SDBD" {
+            self.sdbd = Some(asm.address + 1);
+        }
+        if sdbd && !matches!(form, Indirect | Immediate) {
+            asm.error(&format!(
+                "SDBD must be followed by an immediate or indirect instruction, not {}",
+                i.mnemonic
+            ));
+        }
+
+        match form {
+            Implied => {
+                asm.no_parameter(i);
+                asm.emit_words(&i.mnemonic, &[opcode], None);
+            }
+            Register(max) => {
+                let r = self.register(asm, i, asm.parameter(i));
+                if r > max {
+                    asm.error(&format!("invalid register for {}", i.mnemonic));
+                }
+                asm.emit_words(&i.mnemonic, &[opcode | r], None);
+            }
+            SameRegister => {
+                let r = self.register(asm, i, asm.parameter(i));
+                asm.emit_words(&i.mnemonic, &[opcode | r << 3 | r], None);
+            }
+            // JR copies into R7 and PSHR stores through R6, with the
+            // register given in the source field
+            RegisterField(shift) => {
+                let r = self.register(asm, i, asm.parameter(i));
+                asm.emit_words(&i.mnemonic, &[opcode | r << shift], None);
+            }
+            RegisterRegister => {
+                let (s, d) = self.two_parameters(asm, i);
+                let s = self.register(asm, i, s);
+                let d = self.register(asm, i, d);
+                asm.emit_words(&i.mnemonic, &[opcode | s << 3 | d], None);
+            }
+            Shift => {
+                let (r, n) = match i.parameters.as_slice() {
+                    [r] => (r, 1),
+                    [r, n] => (r, asm.value(n)),
+                    _ => asm.error(&format!("wrong number of parameters for {}", i.mnemonic)),
+                };
+                let r = self.register(asm, i, r);
+                if r > 3 {
+                    asm.error(&format!("only R0-R3 can be shifted by {}", i.mnemonic));
+                }
+                asm.check(
+                    n == 1 || n == 2,
+                    &format!("invalid shift for {}, must be 1 or 2", i.mnemonic),
+                );
+                let double = if n == 2 { 0x004 } else { 0 };
+                asm.emit_words(&i.mnemonic, &[opcode | double | r], None);
+            }
+            Branch => {
+                let destination = self.address(asm, i, asm.parameter(i));
+                // Displacements count from the next instruction, and
+                // backward ones are stored as their one's complement
+                let next = i64::from(asm.address) + 2;
+                let (opcode, displacement) = if destination >= next {
+                    (opcode, destination - next)
+                } else {
+                    (opcode | 0x020, next - destination - 1)
+                };
+                self.check_word(asm, i, displacement);
+                asm.emit_words(
+                    &i.mnemonic,
+                    &[opcode, displacement as u16],
+                    Some(destination),
+                );
+            }
+            // The return address register is encoded as 0-2 for R4-R6,
+            // 3 meaning that there's no return address to keep
+            Jump(flags) | JumpSubroutine(flags) => {
+                let (register, destination) = if let JumpSubroutine(_) = form {
+                    let (r, d) = self.two_parameters(asm, i);
+                    let r = self.register(asm, i, r);
+                    if !(4..=6).contains(&r) {
+                        asm.error(&format!("{} must use R4, R5 or R6", i.mnemonic));
+                    }
+                    (r - 4, d)
+                } else {
+                    (3, asm.parameter(i))
+                };
+                let destination = self.address(asm, i, destination) as u16;
+                asm.emit_words(
+                    &i.mnemonic,
+                    &[
+                        opcode,
+                        register << 8 | (destination >> 10) << 2 | flags,
+                        destination & 0x3FF,
+                    ],
+                    Some(i64::from(destination)),
+                );
+            }
+            Direct | StoreDirect => {
+                let (a, r) = self.two_parameters(asm, i);
+                let (a, r) = if let StoreDirect = form {
+                    (r, a)
+                } else {
+                    (a, r)
+                };
+                let r = self.register(asm, i, r);
+                let a = self.address(asm, i, a);
+                self.check_word(asm, i, a);
+                asm.emit_words(&i.mnemonic, &[opcode | r, a as u16], Some(a));
+            }
+            Indirect | StoreIndirect => {
+                let (p, r) = self.two_parameters(asm, i);
+                let (p, r) = if let StoreIndirect = form {
+                    (r, p)
+                } else {
+                    (p, r)
+                };
+                let p = self.register(asm, i, p);
+                if !(1..=6).contains(&p) {
+                    asm.error(&format!("{} must use R1-R6 as a pointer", i.mnemonic));
+                }
+                let r = self.register(asm, i, r);
+                asm.emit_words(&i.mnemonic, &[opcode | p << 3 | r], None);
+            }
+            Immediate => {
+                let (v, r) = self.two_parameters(asm, i);
+                let v = match v {
+                    Expression::Immediate(v) => asm.value(v),
+                    _ => asm.error(&format!("wrong parameter type for {}", i.mnemonic)),
+                };
+                let r = self.register(asm, i, r);
+                let opcode = opcode | 0x038 | r;
+                if sdbd {
+                    asm.check(
+                        (0..=65535).contains(&v),
+                        &format!("invalid parameter value for {}", i.mnemonic),
+                    );
+                    asm.emit_words(
+                        &i.mnemonic,
+                        &[opcode, (v & 255) as u16, ((v >> 8) & 255) as u16],
+                        Some(v),
+                    );
+                } else {
+                    self.check_word(asm, i, v);
+                    asm.emit_words(&i.mnemonic, &[opcode, v as u16], Some(v));
+                }
+            }
+        }
+        true
+    }
+
+    fn fill(&self) -> u16 {
+        0x034
+    }
+
+    fn word_addressed(&self) -> bool {
+        true
+    }
+
+    fn start_pass(&mut self) {
+        self.romw = 16;
+        self.sdbd = None;
+    }
+}
+
+impl Cp1610Assembler {
+    // Set the ROM width, 10 bits for original cartridges or 16 bits
+    fn romw(&mut self, asm: &mut Assembly, i: &Instruction) {
+        let w = asm.value(asm.parameter(i));
+        if w != 10 && w != 16 {
+            asm.error("invalid ROM width, must be 10 or 16");
+        }
+        self.romw = w as u32;
+    }
+
+    // One word per value
+    fn decle(&mut self, asm: &mut Assembly, i: &Instruction) {
+        if i.parameters.is_empty() {
+            asm.error("missing parameter for decle");
+        }
+        for p in &i.parameters {
+            let v = asm.value(p);
+            self.check_word(asm, i, v);
+            asm.emit_word((v & 0xFFFF) as u16);
+        }
+    }
+
+    // 16-bit values split across two words, low byte first,
+    // the way SDBD reads them
+    fn bidecle(&mut self, asm: &mut Assembly, i: &Instruction) {
+        if i.parameters.is_empty() {
+            asm.error("missing parameter for bidecle");
+        }
+        for p in &i.parameters {
+            let v = asm.value(p);
+            asm.check((0..=65535).contains(&v), "invalid value for bidecle");
+            asm.emit_word((v & 255) as u16);
+            asm.emit_word(((v >> 8) & 255) as u16);
+        }
+    }
+
+    // Check that a value stored after an opcode fits in a ROM word
+    fn check_word(&self, asm: &Assembly, i: &Instruction, v: i64) {
+        asm.check(
+            (0..1 << self.romw).contains(&v),
+            &format!(
+                "value {} for {} doesn't fit in a {}-bit word",
+                v, i.mnemonic, self.romw
+            ),
+        );
+    }
+
+    fn two_parameters<'i>(
+        &self,
+        asm: &Assembly,
+        i: &'i Instruction,
+    ) -> (&'i Expression, &'i Expression) {
+        match i.parameters.as_slice() {
+            [a, b] => (a, b),
+            _ => asm.error(&format!("wrong number of parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // Register R0-R7, with SP and PC as aliases for R6 and R7
+    fn register(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> u16 {
+        if let Expression::Identifier(s) = e {
+            match s.as_str() {
+                "R0" => return 0,
+                "R1" => return 1,
+                "R2" => return 2,
+                "R3" => return 3,
+                "R4" => return 4,
+                "R5" => return 5,
+                "R6" | "SP" => return 6,
+                "R7" | "PC" => return 7,
+                _ => (),
+            }
+        }
+        asm.error(&format!("expected a register for {}", i.mnemonic))
+    }
+
+    fn address(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> i64 {
+        if let Expression::Immediate(_) = e {
+            asm.error(&format!("wrong parameter type for {}", i.mnemonic));
+        }
+        let p = asm.value(e);
+        asm.check(
+            (0..=65535).contains(&p),
+            &format!("invalid address for {}", i.mnemonic),
+        );
+        p & 65535
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Fairchild F8 (3850) back end, as used in the Fairchild Channel F
+//
+// The accumulator works against 64 bytes of scratchpad. Registers 0-11
+// are addressed directly, while the S, I and D forms go through the
+// ISAR (indirect scratchpad address register), respectively leaving
+// it alone, incrementing it or decrementing it. Addresses are stored
+// high byte first, and branch displacements are relative to the
+// displacement byte itself.
+
+use crate::{Assembly, Backend, Expression, Instruction};
+
+pub struct F8Assembler {}
+
+// Where Channel F cartridges are mapped, the BIOS checks for a 0x55
+// marker there and starts running the cartridge two bytes later
+const CARTRIDGE_START: u32 = 0x0800;
+
+enum Form {
+    Implied,
+    Scratchpad,
+    Immediate,
+    Port,
+    ShortPort,
+    Address,
+    Branch,
+    // BT and BF, which take the condition bits before the target
+    ConditionBranch(i64),
+}
+
+impl Backend for F8Assembler {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool {
+        use Form::*;
+
+        let (opcode, form) = match i.mnemonic.as_str() {
+            "cartridge" => {
+                self.cartridge(asm, i);
+                return true;
+            }
+            "LR" => {
+                self.lr(asm, i);
+                return true;
+            }
+            "LIS" => {
+                self.number(asm, i, 0x70, 15);
+                return true;
+            }
+            "LISU" => {
+                self.number(asm, i, 0x60, 7);
+                return true;
+            }
+            "LISL" => {
+                self.number(asm, i, 0x68, 7);
+                return true;
+            }
+            "SR" | "SL" => {
+                self.shift(asm, i);
+                return true;
+            }
+            "PK" => (0x0C, Implied),
+            "LM" => (0x16, Implied),
+            "ST" => (0x17, Implied),
+            "COM" => (0x18, Implied),
+            "LNK" => (0x19, Implied),
+            "DI" => (0x1A, Implied),
+            "EI" => (0x1B, Implied),
+            "POP" => (0x1C, Implied),
+            "INC" => (0x1F, Implied),
+            "LI" => (0x20, Immediate),
+            "NI" => (0x21, Immediate),
+            "OI" => (0x22, Immediate),
+            "XI" => (0x23, Immediate),
+            "AI" => (0x24, Immediate),
+            "CI" => (0x25, Immediate),
+            "IN" => (0x26, Port),
+            "OUT" => (0x27, Port),
+            "PI" => (0x28, Address),
+            "JMP" => (0x29, Address),
+            "DCI" => (0x2A, Address),
+            "NOP" => (0x2B, Implied),
+            "XDC" => (0x2C, Implied),
+            "DS" => (0x30, Scratchpad),
+            "CLR" => (0x70, Implied),
+            "BT" => (0x80, ConditionBranch(7)),
+            "BP" => (0x81, Branch),
+            "BC" => (0x82, Branch),
+            "BZ" => (0x84, Branch),
+            "AM" => (0x88, Implied),
+            "AMD" => (0x89, Implied),
+            "NM" => (0x8A, Implied),
+            "OM" => (0x8B, Implied),
+            "XM" => (0x8C, Implied),
+            "CM" => (0x8D, Implied),
+            "ADC" => (0x8E, Implied),
+            "BR7" => (0x8F, Branch),
+            "BF" => (0x90, ConditionBranch(15)),
+            "BR" => (0x90, Branch),
+            "BM" => (0x91, Branch),
+            "BNC" => (0x92, Branch),
+            "BNZ" => (0x94, Branch),
+            "BNO" => (0x98, Branch),
+            "INS" => (0xA0, ShortPort),
+            "OUTS" => (0xB0, ShortPort),
+            "AS" => (0xC0, Scratchpad),
+            "ASD" => (0xD0, Scratchpad),
+            "XS" => (0xE0, Scratchpad),
+            "NS" => (0xF0, Scratchpad),
+            _ => return false,
+        };
+        match form {
+            Implied => {
+                asm.no_parameter(i);
+                asm.emit(&i.mnemonic, &[opcode], None);
+            }
+            Scratchpad => {
+                let r = self.scratchpad(asm, i, asm.parameter(i));
+                asm.emit(&i.mnemonic, &[opcode | r], None);
+            }
+            Immediate | Port => {
+                let p = self.byte(asm, asm.parameter(i));
+                asm.check(
+                    (-128..=255).contains(&p),
+                    &format!("invalid parameter value for {}", i.mnemonic),
+                );
+                asm.emit(&i.mnemonic, &[opcode, (p & 255) as u8], Some(p));
+            }
+            ShortPort => {
+                self.number(asm, i, opcode, 15);
+            }
+            Address => {
+                let p = self.address(asm, i, asm.parameter(i));
+                asm.emit(
+                    &i.mnemonic,
+                    &[opcode, (p >> 8) as u8, (p & 255) as u8],
+                    Some(i64::from(p)),
+                );
+            }
+            Branch => {
+                let destination = self.address(asm, i, asm.parameter(i));
+                self.branch(asm, i, opcode, destination);
+            }
+            ConditionBranch(max) => {
+                let (t, destination) = match i.parameters.as_slice() {
+                    [t, d] => (t, d),
+                    _ => asm.error(&format!("wrong number of parameters for {}", i.mnemonic)),
+                };
+                let t = asm.value(t);
+                asm.check(
+                    (0..=max).contains(&t),
+                    &format!("invalid condition for {}", i.mnemonic),
+                );
+                let destination = self.address(asm, i, destination);
+                self.branch(asm, i, opcode | (t & max) as u8, destination);
+            }
+        }
+        true
+    }
+
+    fn fill(&self) -> u16 {
+        0x2B
+    }
+}
+
+impl F8Assembler {
+    // Channel F cartridge header: the 0x55 marker that the BIOS looks
+    // for, followed by a NOP as the byte after it is skipped
+    fn cartridge(&self, asm: &mut Assembly, i: &Instruction) {
+        asm.no_parameter(i);
+        if asm.address != CARTRIDGE_START {
+            asm.error(&format!(
+                "cartridge header must be at {}, not {}",
+                CARTRIDGE_START, asm.address
+            ));
+        }
+        asm.emit_byte(0x55);
+        asm.emit_byte(0x2B);
+    }
+
+    // LR, which covers all the register-to-register transfers
+    fn lr(&self, asm: &mut Assembly, i: &Instruction) {
+        let (destination, source) = match i.parameters.as_slice() {
+            [d, s] => (d, s),
+            _ => asm.error("wrong number of parameters for LR"),
+        };
+        let names = (self.name(destination), self.name(source));
+        let opcode = match names {
+            (Some("A"), Some("KU")) => 0x00,
+            (Some("A"), Some("KL")) => 0x01,
+            (Some("A"), Some("QU")) => 0x02,
+            (Some("A"), Some("QL")) => 0x03,
+            (Some("KU"), Some("A")) => 0x04,
+            (Some("KL"), Some("A")) => 0x05,
+            (Some("QU"), Some("A")) => 0x06,
+            (Some("QL"), Some("A")) => 0x07,
+            (Some("K"), Some("P")) => 0x08,
+            (Some("P"), Some("K")) => 0x09,
+            (Some("A"), Some("IS")) => 0x0A,
+            (Some("IS"), Some("A")) => 0x0B,
+            (Some("P0"), Some("Q")) => 0x0D,
+            (Some("Q"), Some("DC")) => 0x0E,
+            (Some("DC"), Some("Q")) => 0x0F,
+            (Some("DC"), Some("H")) => 0x10,
+            (Some("H"), Some("DC")) => 0x11,
+            (Some("W"), Some("J")) => 0x1D,
+            (Some("J"), Some("W")) => 0x1E,
+            (Some("A"), _) => 0x40 | self.scratchpad(asm, i, source),
+            (_, Some("A")) => 0x50 | self.scratchpad(asm, i, destination),
+            _ => asm.error("invalid registers for LR"),
+        };
+        asm.emit(&i.mnemonic, &[opcode], None);
+    }
+
+    // SR and SL, which only shift by 1 or 4
+    fn shift(&self, asm: &mut Assembly, i: &Instruction) {
+        let opcode = match (i.mnemonic.as_str(), asm.value(asm.parameter(i))) {
+            ("SR", 1) => 0x12,
+            ("SL", 1) => 0x13,
+            ("SR", 4) => 0x14,
+            ("SL", 4) => 0x15,
+            _ => asm.error(&format!("invalid shift for {}, must be 1 or 4", i.mnemonic)),
+        };
+        asm.emit(&i.mnemonic, &[opcode], None);
+    }
+
+    // Instructions with a small number in the low bits of the opcode
+    fn number(&self, asm: &mut Assembly, i: &Instruction, opcode: u8, max: i64) {
+        let n = self.byte(asm, asm.parameter(i));
+        asm.check(
+            (0..=max).contains(&n),
+            &format!("invalid parameter value for {}", i.mnemonic),
+        );
+        asm.emit(&i.mnemonic, &[opcode | (n & max) as u8], None);
+    }
+
+    fn branch(&self, asm: &mut Assembly, i: &Instruction, opcode: u8, destination: u32) {
+        let offset = i64::from(destination) - i64::from(asm.address) - 1;
+        asm.check(
+            (-128..=127).contains(&offset),
+            &format!("branch out of range for {}", i.mnemonic),
+        );
+        asm.emit(
+            &i.mnemonic,
+            &[opcode, (offset & 255) as u8],
+            Some(offset & 255),
+        );
+    }
+
+    fn name<'e>(&self, e: &'e Expression) -> Option<&'e str> {
+        match e {
+            Expression::Identifier(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    // Scratchpad register, 0-11 directly or through the ISAR
+    fn scratchpad(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> u8 {
+        match self.name(e) {
+            Some("J") => return 9,
+            Some("HU") => return 10,
+            Some("HL") => return 11,
+            Some("S") => return 12,
+            Some("I") => return 13,
+            Some("D") => return 14,
+            _ => (),
+        }
+        if let Expression::Immediate(_) = e {
+            asm.error(&format!("wrong parameter type for {}", i.mnemonic));
+        }
+        let r = asm.value(e);
+        asm.check(
+            (0..=11).contains(&r),
+            &format!("invalid scratchpad register for {}", i.mnemonic),
+        );
+        (r & 15) as u8
+    }
+
+    // Byte parameter, where the immediate marker is optional
+    fn byte(&self, asm: &Assembly, e: &Expression) -> i64 {
+        match e {
+            Expression::Immediate(e) => asm.value(e),
+            e => asm.value(e),
+        }
+    }
+
+    fn address(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> u32 {
+        if let Expression::Immediate(_) = e {
+            asm.error(&format!("wrong parameter type for {}", i.mnemonic));
+        }
+        let p = asm.value(e);
+        asm.check(
+            (0..=65535).contains(&p),
+            &format!("invalid address for {}", i.mnemonic),
+        );
+        (p & 65535) as u32
+    }
+}
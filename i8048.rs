@@ -0,0 +1,305 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Intel 8048 back end, as used in the Magnavox Odyssey² (Videopac)
+//
+// Program memory is split in two 2K banks. JMP and CALL only hold 11
+// bits of address, the 12th one comes from the bank selected by the
+// last SEL MB0 or SEL MB1, which is tracked in source order. Conditional
+// jumps only replace the low byte of the program counter, so they stay
+// within the 256-byte page of their operand byte.
+
+use crate::{Assembly, Backend, Expression, Instruction};
+
+pub struct I8048Assembler {
+    // Memory bank selected by SEL MB0/MB1
+    bank: u32,
+}
+
+impl I8048Assembler {
+    pub fn new() -> I8048Assembler {
+        I8048Assembler { bank: 0 }
+    }
+}
+
+// Operands, as recognized from their syntax
+enum Operand<'e> {
+    A,
+    Register(u8),
+    // @R0 and @R1
+    IndirectRegister(u8),
+    // @A
+    IndirectA,
+    Immediate(&'e Expression),
+    // Any other reserved name, like C, PSW or P1
+    Name(&'e str),
+    Value(&'e Expression),
+}
+
+// Reserved names that can appear as operands
+const NAMES: [&str; 21] = [
+    "C", "F0", "F1", "I", "TCNTI", "T", "PSW", "BUS", "P1", "P2", "P4", "P5", "P6", "P7", "MB0",
+    "MB1", "RB0", "RB1", "CLK", "CNT", "TCNT",
+];
+
+impl Backend for I8048Assembler {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool {
+        use Operand::*;
+
+        let m = i.mnemonic.as_str();
+        if !self.known(m) {
+            return false;
+        }
+        let operands: Vec<Operand> = i.parameters.iter().map(|p| self.operand(asm, p)).collect();
+        match (m, operands.as_slice()) {
+            ("JMP", [Value(a)]) => self.long_jump(asm, i, 0x04, a),
+            ("CALL", [Value(a)]) => self.long_jump(asm, i, 0x14, a),
+            ("JB", [Value(b), Value(a)]) => {
+                let b = asm.value(b);
+                asm.check((0..=7).contains(&b), "invalid bit for JB");
+                self.conditional_jump(asm, i, 0x12 | ((b & 7) << 5) as u8, a);
+            }
+            ("DJNZ", [Register(r), Value(a)]) => self.conditional_jump(asm, i, 0xE8 | r, a),
+            (_, [Value(a)]) if self.conditional_opcode(m).is_some() => {
+                let opcode = self.conditional_opcode(m).unwrap();
+                self.conditional_jump(asm, i, opcode, a);
+            }
+            (_, [Immediate(_)] | [_, Immediate(_)]) => {
+                let (opcode, v) = match (m, operands.as_slice()) {
+                    ("ADD", [A, Immediate(v)]) => (0x03, v),
+                    ("ADDC", [A, Immediate(v)]) => (0x13, v),
+                    ("MOV", [A, Immediate(v)]) => (0x23, v),
+                    ("ORL", [A, Immediate(v)]) => (0x43, v),
+                    ("ANL", [A, Immediate(v)]) => (0x53, v),
+                    ("XRL", [A, Immediate(v)]) => (0xD3, v),
+                    ("ORL", [Name("BUS"), Immediate(v)]) => (0x88, v),
+                    ("ORL", [Name("P1"), Immediate(v)]) => (0x89, v),
+                    ("ORL", [Name("P2"), Immediate(v)]) => (0x8A, v),
+                    ("ANL", [Name("BUS"), Immediate(v)]) => (0x98, v),
+                    ("ANL", [Name("P1"), Immediate(v)]) => (0x99, v),
+                    ("ANL", [Name("P2"), Immediate(v)]) => (0x9A, v),
+                    ("MOV", [IndirectRegister(r), Immediate(v)]) => (0xB0 | r, v),
+                    ("MOV", [Register(r), Immediate(v)]) => (0xB8 | r, v),
+                    _ => asm.error(&format!("invalid parameters for {}", m)),
+                };
+                let v = asm.value(v);
+                asm.check(
+                    (-128..=255).contains(&v),
+                    &format!("invalid parameter value for {}", m),
+                );
+                asm.emit(m, &[opcode, (v & 255) as u8], Some(v));
+            }
+            _ => {
+                let opcode = match (m, operands.as_slice()) {
+                    ("NOP", []) => 0x00,
+                    ("RET", []) => 0x83,
+                    ("RETR", []) => 0x93,
+                    ("OUTL", [Name("BUS"), A]) => 0x02,
+                    ("EN", [Name("I")]) => 0x05,
+                    ("DEC", [A]) => 0x07,
+                    ("INS", [A, Name("BUS")]) => 0x08,
+                    ("IN", [A, Name("P1")]) => 0x09,
+                    ("IN", [A, Name("P2")]) => 0x0A,
+                    ("MOVD", [A, Name(p)]) => 0x0C | self.expander_port(asm, p),
+                    ("INC", [IndirectRegister(r)]) => 0x10 | r,
+                    ("DIS", [Name("I")]) => 0x15,
+                    ("INC", [A]) => 0x17,
+                    ("INC", [Register(r)]) => 0x18 | r,
+                    ("XCH", [A, IndirectRegister(r)]) => 0x20 | r,
+                    ("EN", [Name("TCNTI")]) => 0x25,
+                    ("CLR", [A]) => 0x27,
+                    ("XCH", [A, Register(r)]) => 0x28 | r,
+                    ("XCHD", [A, IndirectRegister(r)]) => 0x30 | r,
+                    ("DIS", [Name("TCNTI")]) => 0x35,
+                    ("CPL", [A]) => 0x37,
+                    ("OUTL", [Name("P1"), A]) => 0x39,
+                    ("OUTL", [Name("P2"), A]) => 0x3A,
+                    ("MOVD", [Name(p), A]) => 0x3C | self.expander_port(asm, p),
+                    ("ORL", [A, IndirectRegister(r)]) => 0x40 | r,
+                    ("MOV", [A, Name("T")]) => 0x42,
+                    ("STRT", [Name("CNT")]) => 0x45,
+                    ("SWAP", [A]) => 0x47,
+                    ("ORL", [A, Register(r)]) => 0x48 | r,
+                    ("ANL", [A, IndirectRegister(r)]) => 0x50 | r,
+                    ("STRT", [Name("T")]) => 0x55,
+                    ("DA", [A]) => 0x57,
+                    ("ANL", [A, Register(r)]) => 0x58 | r,
+                    ("ADD", [A, IndirectRegister(r)]) => 0x60 | r,
+                    ("MOV", [Name("T"), A]) => 0x62,
+                    ("STOP", [Name("TCNT")]) => 0x65,
+                    ("RRC", [A]) => 0x67,
+                    ("ADD", [A, Register(r)]) => 0x68 | r,
+                    ("ADDC", [A, IndirectRegister(r)]) => 0x70 | r,
+                    ("ENT0", [Name("CLK")]) => 0x75,
+                    ("RR", [A]) => 0x77,
+                    ("ADDC", [A, Register(r)]) => 0x78 | r,
+                    ("MOVX", [A, IndirectRegister(r)]) => 0x80 | r,
+                    ("ORLD", [Name(p), A]) => 0x8C | self.expander_port(asm, p),
+                    ("MOVX", [IndirectRegister(r), A]) => 0x90 | r,
+                    ("CLR", [Name("F0")]) => 0x85,
+                    ("CPL", [Name("F0")]) => 0x95,
+                    ("CLR", [Name("C")]) => 0x97,
+                    ("ANLD", [Name(p), A]) => 0x9C | self.expander_port(asm, p),
+                    ("MOV", [IndirectRegister(r), A]) => 0xA0 | r,
+                    ("MOVP", [A, IndirectA]) => 0xA3,
+                    ("CLR", [Name("F1")]) => 0xA5,
+                    ("CPL", [Name("C")]) => 0xA7,
+                    ("MOV", [Register(r), A]) => 0xA8 | r,
+                    ("JMPP", [IndirectA]) => 0xB3,
+                    ("CPL", [Name("F1")]) => 0xB5,
+                    ("SEL", [Name("RB0")]) => 0xC5,
+                    ("MOV", [A, Name("PSW")]) => 0xC7,
+                    ("DEC", [Register(r)]) => 0xC8 | r,
+                    ("XRL", [A, IndirectRegister(r)]) => 0xD0 | r,
+                    ("SEL", [Name("RB1")]) => 0xD5,
+                    ("MOV", [Name("PSW"), A]) => 0xD7,
+                    ("XRL", [A, Register(r)]) => 0xD8 | r,
+                    ("MOVP3", [A, IndirectA]) => 0xE3,
+                    ("SEL", [Name("MB0")]) => {
+                        self.bank = 0;
+                        0xE5
+                    }
+                    ("RL", [A]) => 0xE7,
+                    ("MOV", [A, IndirectRegister(r)]) => 0xF0 | r,
+                    ("SEL", [Name("MB1")]) => {
+                        self.bank = 1;
+                        0xF5
+                    }
+                    ("RLC", [A]) => 0xF7,
+                    ("MOV", [A, Register(r)]) => 0xF8 | r,
+                    _ => asm.error(&format!("invalid parameters for {}", m)),
+                };
+                asm.emit(m, &[opcode], None);
+            }
+        }
+        true
+    }
+
+    fn fill(&self) -> u16 {
+        0x00
+    }
+
+    fn start_pass(&mut self) {
+        self.bank = 0;
+    }
+}
+
+impl I8048Assembler {
+    fn operand<'e>(&self, asm: &Assembly, e: &'e Expression) -> Operand<'e> {
+        match e {
+            Expression::Identifier(s) => match s.as_str() {
+                "A" => Operand::A,
+                "R0" | "R1" | "R2" | "R3" | "R4" | "R5" | "R6" | "R7" => {
+                    Operand::Register(s.as_bytes()[1] - b'0')
+                }
+                s if NAMES.contains(&s) => Operand::Name(s),
+                _ => Operand::Value(e),
+            },
+            Expression::Indirect(r) => match r.as_ref() {
+                Expression::Identifier(s) if s == "R0" => Operand::IndirectRegister(0),
+                Expression::Identifier(s) if s == "R1" => Operand::IndirectRegister(1),
+                Expression::Identifier(s) if s == "A" => Operand::IndirectA,
+                _ => asm.error("only @R0, @R1 and @A are valid indirect parameters"),
+            },
+            Expression::Immediate(v) => Operand::Immediate(v),
+            _ => Operand::Value(e),
+        }
+    }
+
+    fn known(&self, m: &str) -> bool {
+        [
+            "ADD", "ADDC", "ANL", "ANLD", "CLR", "CPL", "DA", "DEC", "DIS", "EN", "ENT0", "IN",
+            "INC", "INS", "JMPP", "MOV", "MOVD", "MOVP", "MOVP3", "MOVX", "NOP", "ORL", "ORLD",
+            "OUTL", "RET", "RETR", "RL", "RLC", "RR", "RRC", "SEL", "STOP", "STRT", "SWAP", "XCH",
+            "XCHD", "XRL", "JMP", "CALL", "JB", "DJNZ",
+        ]
+        .contains(&m)
+            || self.conditional_opcode(m).is_some()
+    }
+
+    fn conditional_opcode(&self, m: &str) -> Option<u8> {
+        match m {
+            "JTF" => Some(0x16),
+            "JNT0" => Some(0x26),
+            "JT0" => Some(0x36),
+            "JNT1" => Some(0x46),
+            "JT1" => Some(0x56),
+            "JF1" => Some(0x76),
+            "JNI" => Some(0x86),
+            "JNZ" => Some(0x96),
+            "JF0" => Some(0xB6),
+            "JZ" => Some(0xC6),
+            "JNC" => Some(0xE6),
+            "JC" => Some(0xF6),
+            _ => None,
+        }
+    }
+
+    // P4-P7, on an 8243 expander
+    fn expander_port(&self, asm: &Assembly, p: &str) -> u8 {
+        match p {
+            "P4" => 0,
+            "P5" => 1,
+            "P6" => 2,
+            "P7" => 3,
+            _ => asm.error("expander port must be P4-P7"),
+        }
+    }
+
+    fn address(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> u32 {
+        let p = asm.value(e);
+        asm.check(
+            (0..=4095).contains(&p),
+            &format!("invalid address for {}", i.mnemonic),
+        );
+        (p & 4095) as u32
+    }
+
+    // JMP and CALL, within the 2K bank selected by SEL MB0/MB1
+    fn long_jump(&self, asm: &mut Assembly, i: &Instruction, opcode: u8, e: &Expression) {
+        let destination = self.address(asm, i, e);
+        if destination >> 11 != self.bank {
+            asm.warning(&format!(
+                "{} to {} while MB{} is selected",
+                i.mnemonic, destination, self.bank
+            ));
+        }
+        asm.emit(
+            &i.mnemonic,
+            &[
+                opcode | ((destination >> 3) & 0xE0) as u8,
+                (destination & 255) as u8,
+            ],
+            Some(i64::from(destination)),
+        );
+    }
+
+    // Conditional jumps, within the page of their operand byte
+    fn conditional_jump(&self, asm: &mut Assembly, i: &Instruction, opcode: u8, e: &Expression) {
+        let destination = self.address(asm, i, e);
+        let page = (asm.address + 1) >> 8;
+        asm.check(
+            destination >> 8 == page,
+            &format!(
+                "conditional jump {} from {} to {} crosses a page boundary",
+                i.mnemonic, asm.address, destination
+            ),
+        );
+        asm.emit(
+            &i.mnemonic,
+            &[opcode, (destination & 255) as u8],
+            Some(i64::from(destination)),
+        );
+    }
+}
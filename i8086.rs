@@ -0,0 +1,927 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Intel 8086/8088 back end, as used in the IBM PC and its clones
+//
+// Operands are written destination first. Memory operands go in
+// brackets, as [BX+SI+4], optionally with a segment override as
+// [ES:DI], and BYTE or WORD gives their size when no register does.
+// Plain values are immediates. Values are stored low byte first.
+//
+// JMP and conditional jumps start out short, and switch to the near
+// form when their destination is out of range, which for conditional
+// jumps means the opposite condition jumping over a near JMP. Once a
+// jump is near, it stays near so that the passes settle down. SHORT
+// and NEAR force either form.
+
+use crate::{Assembly, Backend, Expression, Instruction};
+use std::collections::HashSet;
+
+pub struct I8086Assembler {
+    // Lines of the jumps that need the near form
+    near: HashSet<u32>,
+}
+
+impl I8086Assembler {
+    pub fn new() -> I8086Assembler {
+        I8086Assembler {
+            near: HashSet::new(),
+        }
+    }
+}
+
+// Operands, as recognized from their syntax
+enum Operand<'e> {
+    // General register, with the w bit, 0 for 8 bits and 1 for 16 bits
+    Register(u8, u8),
+    Segment(u8),
+    // Memory operand, with the w bit when BYTE or WORD is given
+    Memory(Option<u8>, ModRm),
+    // FAR [...], a segment and offset in memory
+    FarMemory(ModRm),
+    Immediate(&'e Expression),
+    Short(&'e Expression),
+    Near(&'e Expression),
+    // Direct far address, segment:offset
+    Far(&'e Expression, &'e Expression),
+}
+
+// The ModR/M byte of an operand, without its reg field, and the bytes
+// that go around it
+#[derive(Clone)]
+struct ModRm {
+    // Segment override prefix
+    prefix: Option<u8>,
+    modrm: u8,
+    displacement: Vec<u8>,
+    parameter: Option<i64>,
+}
+
+// The ModR/M encoding of a direct address, with no base or index
+const DIRECT: u8 = 0x06;
+
+impl Backend for I8086Assembler {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool {
+        use Operand::*;
+
+        let m = i.mnemonic.as_str();
+        if m == "word" {
+            self.word(asm, i);
+            return true;
+        }
+        if !self.known(m) {
+            return false;
+        }
+        let operands: Vec<Operand> = i
+            .parameters
+            .iter()
+            .map(|p| self.operand(asm, i, p))
+            .collect();
+        let operands = operands.as_slice();
+        match m {
+            "ADD" => self.arithmetic(asm, i, 0, operands),
+            "OR" => self.arithmetic(asm, i, 1, operands),
+            "ADC" => self.arithmetic(asm, i, 2, operands),
+            "SBB" => self.arithmetic(asm, i, 3, operands),
+            "AND" => self.arithmetic(asm, i, 4, operands),
+            "SUB" => self.arithmetic(asm, i, 5, operands),
+            "XOR" => self.arithmetic(asm, i, 6, operands),
+            "CMP" => self.arithmetic(asm, i, 7, operands),
+            "TEST" => self.test(asm, i, operands),
+            "NOT" => self.unary(asm, i, 2, operands),
+            "NEG" => self.unary(asm, i, 3, operands),
+            "MUL" => self.unary(asm, i, 4, operands),
+            "IMUL" => self.unary(asm, i, 5, operands),
+            "DIV" => self.unary(asm, i, 6, operands),
+            "IDIV" => self.unary(asm, i, 7, operands),
+            "INC" => self.increment(asm, i, 0, operands),
+            "DEC" => self.increment(asm, i, 1, operands),
+            "ROL" => self.shift(asm, i, 0, operands),
+            "ROR" => self.shift(asm, i, 1, operands),
+            "RCL" => self.shift(asm, i, 2, operands),
+            "RCR" => self.shift(asm, i, 3, operands),
+            "SHL" | "SAL" => self.shift(asm, i, 4, operands),
+            "SHR" => self.shift(asm, i, 5, operands),
+            "SAR" => self.shift(asm, i, 7, operands),
+            "MOV" => self.mov(asm, i, operands),
+            "XCHG" => self.xchg(asm, i, operands),
+            "PUSH" => self.stack(asm, i, false, operands),
+            "POP" => self.stack(asm, i, true, operands),
+            "IN" => match operands {
+                [Register(w, 0), Immediate(p)] => {
+                    let p = self.port(asm, i, p);
+                    asm.emit(m, &[0xE4 | w, p], Some(i64::from(p)));
+                }
+                [Register(w, 0), Register(1, 2)] => asm.emit(m, &[0xEC | w], None),
+                _ => asm.error(&format!("invalid parameters for {}", m)),
+            },
+            "OUT" => match operands {
+                [Immediate(p), Register(w, 0)] => {
+                    let p = self.port(asm, i, p);
+                    asm.emit(m, &[0xE6 | w, p], Some(i64::from(p)));
+                }
+                [Register(1, 2), Register(w, 0)] => asm.emit(m, &[0xEE | w], None),
+                _ => asm.error(&format!("invalid parameters for {}", m)),
+            },
+            "LEA" => self.load_address(asm, i, 0x8D, operands),
+            "LDS" => self.load_address(asm, i, 0xC5, operands),
+            "LES" => self.load_address(asm, i, 0xC4, operands),
+            "INT" => match operands {
+                [Immediate(v)] => {
+                    let v = self.port(asm, i, v);
+                    asm.emit(m, &[0xCD, v], Some(i64::from(v)));
+                }
+                _ => asm.error(&format!("invalid parameters for {}", m)),
+            },
+            // The base is 10 unless given
+            "AAM" | "AAD" => {
+                let opcode = if m == "AAM" { 0xD4 } else { 0xD5 };
+                match operands {
+                    [] => asm.emit(m, &[opcode, 0x0A], None),
+                    [Immediate(v)] => {
+                        let v = self.port(asm, i, v);
+                        asm.emit(m, &[opcode, v], Some(i64::from(v)));
+                    }
+                    _ => asm.error(&format!("invalid parameters for {}", m)),
+                }
+            }
+            "RET" | "RETF" => {
+                let opcode = if m == "RET" { 0xC2 } else { 0xCA };
+                match operands {
+                    [] => asm.emit(m, &[opcode | 1], None),
+                    [Immediate(e)] => {
+                        let v = self.immediate(asm, i, e, 1);
+                        asm.emit(m, &[opcode, v[0], v[1]], Some(asm.value(e)));
+                    }
+                    _ => asm.error(&format!("invalid parameters for {}", m)),
+                }
+            }
+            "JMP" => self.jmp(asm, i, operands),
+            "CALL" => self.call(asm, i, operands),
+            "LOOPNE" | "LOOPNZ" => self.short_jump(asm, i, 0xE0, operands),
+            "LOOPE" | "LOOPZ" => self.short_jump(asm, i, 0xE1, operands),
+            "LOOP" => self.short_jump(asm, i, 0xE2, operands),
+            "JCXZ" => self.short_jump(asm, i, 0xE3, operands),
+            "REPNE" | "REPNZ" => self.repeat(asm, i, 0xF2, operands),
+            "REP" | "REPE" | "REPZ" => self.repeat(asm, i, 0xF3, operands),
+            _ => {
+                if let Some(opcode) = self.conditional_opcode(m) {
+                    self.conditional_jump(asm, i, opcode, operands);
+                } else if let Some(opcode) = self.implied_opcode(m) {
+                    asm.no_parameter(i);
+                    asm.emit(m, &[opcode], None);
+                }
+            }
+        }
+        true
+    }
+
+    fn fill(&self) -> u16 {
+        0x90
+    }
+
+    fn segmented(&self) -> bool {
+        true
+    }
+
+    // Linear addresses cover the 1MB address space
+    fn max_address(&self) -> u32 {
+        0xFFFFF
+    }
+}
+
+impl I8086Assembler {
+    // 16-bit values, low byte first
+    fn word(&self, asm: &mut Assembly, i: &Instruction) {
+        if i.parameters.is_empty() {
+            asm.error("missing parameter for word");
+        }
+        for p in &i.parameters {
+            if let Expression::Immediate(_) = p {
+                asm.error("wrong parameter type for word");
+            }
+            let v = asm.value(p);
+            asm.check((-32768..=65535).contains(&v), "invalid value for word");
+            asm.emit_byte((v & 255) as u8);
+            asm.emit_byte(((v >> 8) & 255) as u8);
+        }
+    }
+
+    fn known(&self, m: &str) -> bool {
+        [
+            "ADD", "OR", "ADC", "SBB", "AND", "SUB", "XOR", "CMP", "TEST", "NOT", "NEG", "MUL",
+            "IMUL", "DIV", "IDIV", "INC", "DEC", "ROL", "ROR", "RCL", "RCR", "SHL", "SAL", "SHR",
+            "SAR", "MOV", "XCHG", "PUSH", "POP", "IN", "OUT", "LEA", "LDS", "LES", "INT", "AAM",
+            "AAD", "RET", "RETF", "JMP", "CALL", "LOOPNE", "LOOPNZ", "LOOPE", "LOOPZ", "LOOP",
+            "JCXZ", "REPNE", "REPNZ", "REP", "REPE", "REPZ",
+        ]
+        .contains(&m)
+            || self.conditional_opcode(m).is_some()
+            || self.implied_opcode(m).is_some()
+    }
+
+    fn implied_opcode(&self, m: &str) -> Option<u8> {
+        match m {
+            "ES" => Some(0x26),
+            "DAA" => Some(0x27),
+            "CS" => Some(0x2E),
+            "DAS" => Some(0x2F),
+            "SS" => Some(0x36),
+            "AAA" => Some(0x37),
+            "DS" => Some(0x3E),
+            "AAS" => Some(0x3F),
+            "NOP" => Some(0x90),
+            "CBW" => Some(0x98),
+            "CWD" => Some(0x99),
+            "WAIT" => Some(0x9B),
+            "PUSHF" => Some(0x9C),
+            "POPF" => Some(0x9D),
+            "SAHF" => Some(0x9E),
+            "LAHF" => Some(0x9F),
+            "MOVSB" => Some(0xA4),
+            "MOVSW" => Some(0xA5),
+            "CMPSB" => Some(0xA6),
+            "CMPSW" => Some(0xA7),
+            "STOSB" => Some(0xAA),
+            "STOSW" => Some(0xAB),
+            "LODSB" => Some(0xAC),
+            "LODSW" => Some(0xAD),
+            "SCASB" => Some(0xAE),
+            "SCASW" => Some(0xAF),
+            "INT3" => Some(0xCC),
+            "INTO" => Some(0xCE),
+            "IRET" => Some(0xCF),
+            "XLAT" | "XLATB" => Some(0xD7),
+            "LOCK" => Some(0xF0),
+            "HLT" => Some(0xF4),
+            "CMC" => Some(0xF5),
+            "CLC" => Some(0xF8),
+            "STC" => Some(0xF9),
+            "CLI" => Some(0xFA),
+            "STI" => Some(0xFB),
+            "CLD" => Some(0xFC),
+            "STD" => Some(0xFD),
+            _ => None,
+        }
+    }
+
+    // Short conditional jumps, where flipping the low bit of the
+    // opcode flips the condition
+    fn conditional_opcode(&self, m: &str) -> Option<u8> {
+        match m {
+            "JO" => Some(0x70),
+            "JNO" => Some(0x71),
+            "JB" | "JC" | "JNAE" => Some(0x72),
+            "JAE" | "JNB" | "JNC" => Some(0x73),
+            "JE" | "JZ" => Some(0x74),
+            "JNE" | "JNZ" => Some(0x75),
+            "JBE" | "JNA" => Some(0x76),
+            "JA" | "JNBE" => Some(0x77),
+            "JS" => Some(0x78),
+            "JNS" => Some(0x79),
+            "JP" | "JPE" => Some(0x7A),
+            "JNP" | "JPO" => Some(0x7B),
+            "JL" | "JNGE" => Some(0x7C),
+            "JGE" | "JNL" => Some(0x7D),
+            "JLE" | "JNG" => Some(0x7E),
+            "JG" | "JNLE" => Some(0x7F),
+            _ => None,
+        }
+    }
+
+    fn operand<'e>(&self, asm: &Assembly, i: &Instruction, e: &'e Expression) -> Operand<'e> {
+        match e {
+            Expression::Identifier(s) => {
+                if let Some(r) = self.register(s, 0) {
+                    return Operand::Register(0, r);
+                }
+                if let Some(r) = self.register(s, 1) {
+                    return Operand::Register(1, r);
+                }
+                if let Some(s) = self.segment_register(s) {
+                    return Operand::Segment(s);
+                }
+                Operand::Immediate(e)
+            }
+            Expression::Immediate(e) => Operand::Immediate(e),
+            Expression::Memory(e) => Operand::Memory(None, self.address(asm, i, e)),
+            Expression::Segment(s, o) => Operand::Far(s, o),
+            Expression::Qualified(q, e) => match (q.as_str(), e.as_ref()) {
+                ("BYTE", Expression::Memory(e)) => {
+                    Operand::Memory(Some(0), self.address(asm, i, e))
+                }
+                ("WORD", Expression::Memory(e)) => {
+                    Operand::Memory(Some(1), self.address(asm, i, e))
+                }
+                ("FAR", Expression::Memory(e)) => Operand::FarMemory(self.address(asm, i, e)),
+                ("FAR", Expression::Segment(s, o)) => Operand::Far(s, o),
+                ("SHORT", e) => Operand::Short(e),
+                ("NEAR", e) => Operand::Near(e),
+                _ => asm.error(&format!("invalid {} parameter for {}", q, i.mnemonic)),
+            },
+            Expression::Indirect(_)
+            | Expression::Deferred(_)
+            | Expression::PostIncrement(_)
+            | Expression::PostDecrement(_)
+            | Expression::Indexed(_, _) => {
+                asm.error(&format!("wrong parameter type for {}", i.mnemonic))
+            }
+            _ => Operand::Immediate(e),
+        }
+    }
+
+    // General registers, in the order of their encoding
+    fn register(&self, s: &str, w: u8) -> Option<u8> {
+        let names = if w == 0 {
+            ["AL", "CL", "DL", "BL", "AH", "CH", "DH", "BH"]
+        } else {
+            ["AX", "CX", "DX", "BX", "SP", "BP", "SI", "DI"]
+        };
+        names.iter().position(|n| *n == s).map(|r| r as u8)
+    }
+
+    fn segment_register(&self, s: &str) -> Option<u8> {
+        ["ES", "CS", "SS", "DS"]
+            .iter()
+            .position(|n| *n == s)
+            .map(|r| r as u8)
+    }
+
+    // Memory operand, as an optional segment override, up to one base
+    // register (BX or BP), up to one index register (SI or DI) and a
+    // displacement, which is as short as possible when it's a constant
+    fn address(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> ModRm {
+        let (prefix, e) = match e {
+            Expression::Segment(s, e) => match s.as_ref() {
+                Expression::Identifier(s) if self.segment_register(s).is_some() => (
+                    Some(0x26 | self.segment_register(s).unwrap() << 3),
+                    e.as_ref(),
+                ),
+                _ => asm.error(&format!("invalid segment override for {}", i.mnemonic)),
+            },
+            e => (None, e),
+        };
+        let mut registers = Vec::new();
+        let mut terms = Vec::new();
+        self.terms(asm, e, false, &mut registers, &mut terms);
+        registers.sort_unstable();
+        let rm = match registers.as_slice() {
+            ["BX", "SI"] => 0,
+            ["BX", "DI"] => 1,
+            ["BP", "SI"] => 2,
+            ["BP", "DI"] => 3,
+            ["SI"] => 4,
+            ["DI"] => 5,
+            ["BP"] => 6,
+            ["BX"] => 7,
+            [] => {
+                let a = self.displacement(asm, i, &terms);
+                return ModRm {
+                    prefix,
+                    modrm: DIRECT,
+                    displacement: vec![(a & 255) as u8, ((a >> 8) & 255) as u8],
+                    parameter: Some(a),
+                };
+            }
+            _ => asm.error(&format!("invalid address registers for {}", i.mnemonic)),
+        };
+        let d = self.displacement(asm, i, &terms);
+        let constant = terms.iter().all(|(_, t)| self.constant(t));
+        // [BP] with no displacement would be a direct address
+        let (modrm, displacement) = if terms.is_empty() && rm != 6 {
+            (rm, vec![])
+        } else if constant && (-128..=127).contains(&d) {
+            (0x40 | rm, vec![(d & 255) as u8])
+        } else {
+            (0x80 | rm, vec![(d & 255) as u8, ((d >> 8) & 255) as u8])
+        };
+        ModRm {
+            prefix,
+            modrm,
+            parameter: if displacement.is_empty() {
+                None
+            } else {
+                Some(d)
+            },
+            displacement,
+        }
+    }
+
+    // Split an address into its registers and the terms of its
+    // displacement, with whether they're subtracted
+    fn terms<'e>(
+        &self,
+        asm: &Assembly,
+        e: &'e Expression,
+        negative: bool,
+        registers: &mut Vec<&'e str>,
+        terms: &mut Vec<(bool, &'e Expression)>,
+    ) {
+        match e {
+            Expression::Sum(a, b) => {
+                self.terms(asm, a, negative, registers, terms);
+                self.terms(asm, b, negative, registers, terms);
+            }
+            Expression::Difference(a, b) => {
+                self.terms(asm, a, negative, registers, terms);
+                self.terms(asm, b, !negative, registers, terms);
+            }
+            Expression::Identifier(s) if ["BX", "BP", "SI", "DI"].contains(&s.as_str()) => {
+                if negative {
+                    asm.error("address registers can't be subtracted");
+                }
+                registers.push(s.as_str());
+            }
+            e => terms.push((negative, e)),
+        }
+    }
+
+    fn displacement(&self, asm: &Assembly, i: &Instruction, terms: &[(bool, &Expression)]) -> i64 {
+        let d = terms.iter().fold(0i64, |d, (negative, t)| {
+            if *negative {
+                d.wrapping_sub(asm.value(t))
+            } else {
+                d.wrapping_add(asm.value(t))
+            }
+        });
+        asm.check(
+            (-32768..=65535).contains(&d),
+            &format!("invalid address for {}", i.mnemonic),
+        );
+        d
+    }
+
+    // Whether an expression has no symbols, in which case its value
+    // can't change from one pass to the next
+    fn constant(&self, e: &Expression) -> bool {
+        match e {
+            Expression::Number(_) => true,
+            Expression::Sum(a, b) | Expression::Difference(a, b) | Expression::Product(a, b) => {
+                self.constant(a) && self.constant(b)
+            }
+            Expression::Negation(e) => self.constant(e),
+            Expression::Parenthesized(l) => l.iter().all(|e| self.constant(e)),
+            _ => false,
+        }
+    }
+
+    // Register or memory operand, as a ModR/M encoding and the w bit
+    // when it's known
+    fn rm(&self, o: &Operand) -> Option<(Option<u8>, ModRm)> {
+        match o {
+            Operand::Register(w, r) => Some((
+                Some(*w),
+                ModRm {
+                    prefix: None,
+                    modrm: 0xC0 | r,
+                    displacement: vec![],
+                    parameter: None,
+                },
+            )),
+            Operand::Memory(w, rm) => Some((*w, rm.clone())),
+            _ => None,
+        }
+    }
+
+    // Operand size, from whichever operands know about it
+    fn width(&self, asm: &Assembly, i: &Instruction, a: Option<u8>, b: Option<u8>) -> u8 {
+        match (a, b) {
+            (Some(a), Some(b)) if a != b => {
+                asm.error(&format!("operand size mismatch for {}", i.mnemonic))
+            }
+            (Some(w), _) | (_, Some(w)) => w,
+            (None, None) => asm.error(&format!(
+                "operand size unknown for {}, use BYTE or WORD",
+                i.mnemonic
+            )),
+        }
+    }
+
+    // Immediate value, one byte or a word
+    fn immediate(&self, asm: &Assembly, i: &Instruction, e: &Expression, w: u8) -> Vec<u8> {
+        let v = asm.value(e);
+        if w == 0 {
+            asm.check(
+                (-128..=255).contains(&v),
+                &format!("invalid parameter value for {}", i.mnemonic),
+            );
+            vec![(v & 255) as u8]
+        } else {
+            asm.check(
+                (-32768..=65535).contains(&v),
+                &format!("invalid parameter value for {}", i.mnemonic),
+            );
+            vec![(v & 255) as u8, ((v >> 8) & 255) as u8]
+        }
+    }
+
+    // Whether an immediate fits in a sign-extended byte in every pass
+    fn small(&self, asm: &Assembly, e: &Expression) -> bool {
+        self.constant(e) && (-128..=127).contains(&asm.value(e))
+    }
+
+    fn port(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> u8 {
+        let v = asm.value(e);
+        asm.check(
+            (0..=255).contains(&v),
+            &format!("invalid parameter value for {}", i.mnemonic),
+        );
+        (v & 255) as u8
+    }
+
+    // Emit an instruction with a ModR/M byte, followed by its
+    // displacement and immediate value, if any
+    fn emit_modrm(
+        &self,
+        asm: &mut Assembly,
+        i: &Instruction,
+        opcode: u8,
+        reg: u8,
+        rm: &ModRm,
+        immediate: &[u8],
+    ) {
+        let mut bytes = Vec::new();
+        bytes.extend(rm.prefix);
+        bytes.push(opcode);
+        bytes.push(rm.modrm | reg << 3);
+        bytes.extend(&rm.displacement);
+        bytes.extend(immediate);
+        let parameter = if immediate.is_empty() {
+            rm.parameter
+        } else {
+            Some(
+                immediate
+                    .iter()
+                    .rev()
+                    .fold(0, |v, b| v << 8 | i64::from(*b)),
+            )
+        };
+        asm.emit(&i.mnemonic, &bytes, parameter);
+    }
+
+    // ADD, OR, ADC, SBB, AND, SUB, XOR and CMP
+    fn arithmetic(&self, asm: &mut Assembly, i: &Instruction, n: u8, operands: &[Operand]) {
+        use Operand::*;
+
+        match operands {
+            [Register(w, 0), Immediate(e)] if *w == 0 || !self.small(asm, e) => {
+                let v = self.immediate(asm, i, e, *w);
+                let mut bytes = vec![0x04 | n << 3 | w];
+                bytes.extend(&v);
+                asm.emit(&i.mnemonic, &bytes, Some(asm.value(e)));
+            }
+            [d, Immediate(e)] if self.rm(d).is_some() => {
+                let (w, rm) = self.rm(d).unwrap();
+                let w = self.width(asm, i, w, None);
+                if w == 1 && self.small(asm, e) {
+                    let v = self.immediate(asm, i, e, 0);
+                    self.emit_modrm(asm, i, 0x83, n, &rm, &v);
+                } else {
+                    let v = self.immediate(asm, i, e, w);
+                    self.emit_modrm(asm, i, 0x80 | w, n, &rm, &v);
+                }
+            }
+            [d, Register(w, r)] if self.rm(d).is_some() => {
+                let (dw, rm) = self.rm(d).unwrap();
+                let w = self.width(asm, i, dw, Some(*w));
+                self.emit_modrm(asm, i, n << 3 | w, *r, &rm, &[]);
+            }
+            [Register(w, r), Memory(mw, rm)] => {
+                let w = self.width(asm, i, Some(*w), *mw);
+                self.emit_modrm(asm, i, n << 3 | 0x02 | w, *r, rm, &[]);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    fn test(&self, asm: &mut Assembly, i: &Instruction, operands: &[Operand]) {
+        use Operand::*;
+
+        match operands {
+            [Register(w, 0), Immediate(e)] => {
+                let v = self.immediate(asm, i, e, *w);
+                let mut bytes = vec![0xA8 | w];
+                bytes.extend(&v);
+                asm.emit(&i.mnemonic, &bytes, Some(asm.value(e)));
+            }
+            [d, Immediate(e)] if self.rm(d).is_some() => {
+                let (w, rm) = self.rm(d).unwrap();
+                let w = self.width(asm, i, w, None);
+                let v = self.immediate(asm, i, e, w);
+                self.emit_modrm(asm, i, 0xF6 | w, 0, &rm, &v);
+            }
+            [d, Register(w, r)] | [Register(w, r), d] if self.rm(d).is_some() => {
+                let (dw, rm) = self.rm(d).unwrap();
+                let w = self.width(asm, i, dw, Some(*w));
+                self.emit_modrm(asm, i, 0x84 | w, *r, &rm, &[]);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // NOT, NEG, MUL, IMUL, DIV and IDIV
+    fn unary(&self, asm: &mut Assembly, i: &Instruction, n: u8, operands: &[Operand]) {
+        match operands {
+            [d] if self.rm(d).is_some() => {
+                let (w, rm) = self.rm(d).unwrap();
+                let w = self.width(asm, i, w, None);
+                self.emit_modrm(asm, i, 0xF6 | w, n, &rm, &[]);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // INC and DEC, with a short form for 16-bit registers
+    fn increment(&self, asm: &mut Assembly, i: &Instruction, n: u8, operands: &[Operand]) {
+        match operands {
+            [Operand::Register(1, r)] => asm.emit(&i.mnemonic, &[0x40 | n << 3 | r], None),
+            [d] if self.rm(d).is_some() => {
+                let (w, rm) = self.rm(d).unwrap();
+                let w = self.width(asm, i, w, None);
+                self.emit_modrm(asm, i, 0xFE | w, n, &rm, &[]);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // Rotations and shifts, by 1 or by CL
+    fn shift(&self, asm: &mut Assembly, i: &Instruction, n: u8, operands: &[Operand]) {
+        let (d, opcode) = match operands {
+            [d, Operand::Register(0, 1)] => (d, 0xD2),
+            [d, Operand::Immediate(c)] => {
+                if asm.value(c) != 1 {
+                    asm.error(&format!("{} can only shift by 1 or CL", i.mnemonic));
+                }
+                (d, 0xD0)
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        };
+        match self.rm(d) {
+            Some((w, rm)) => {
+                let w = self.width(asm, i, w, None);
+                self.emit_modrm(asm, i, opcode | w, n, &rm, &[]);
+            }
+            None => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    fn mov(&self, asm: &mut Assembly, i: &Instruction, operands: &[Operand]) {
+        use Operand::*;
+
+        match operands {
+            // AL and AX have a short form for direct addresses
+            [Register(w, 0), Memory(mw, rm)] | [Memory(mw, rm), Register(w, 0)]
+                if rm.modrm == DIRECT =>
+            {
+                let w = self.width(asm, i, Some(*w), *mw);
+                let store = if let Memory(_, _) = operands[0] { 2 } else { 0 };
+                let mut bytes = Vec::new();
+                bytes.extend(rm.prefix);
+                bytes.push(0xA0 | store | w);
+                bytes.extend(&rm.displacement);
+                asm.emit(&i.mnemonic, &bytes, rm.parameter);
+            }
+            [d, Register(w, r)] if self.rm(d).is_some() => {
+                let (dw, rm) = self.rm(d).unwrap();
+                let w = self.width(asm, i, dw, Some(*w));
+                self.emit_modrm(asm, i, 0x88 | w, *r, &rm, &[]);
+            }
+            [Register(w, r), Memory(mw, rm)] => {
+                let w = self.width(asm, i, Some(*w), *mw);
+                self.emit_modrm(asm, i, 0x8A | w, *r, rm, &[]);
+            }
+            [d, Segment(s)] if self.rm(d).is_some() => {
+                let (w, rm) = self.rm(d).unwrap();
+                self.width(asm, i, w, Some(1));
+                self.emit_modrm(asm, i, 0x8C, *s, &rm, &[]);
+            }
+            [Segment(s), source] if self.rm(source).is_some() => {
+                if *s == 1 {
+                    asm.error("CS can't be loaded with MOV");
+                }
+                let (w, rm) = self.rm(source).unwrap();
+                self.width(asm, i, w, Some(1));
+                self.emit_modrm(asm, i, 0x8E, *s, &rm, &[]);
+            }
+            [Register(w, r), Immediate(e)] => {
+                let v = self.immediate(asm, i, e, *w);
+                let mut bytes = vec![0xB0 | w << 3 | r];
+                bytes.extend(&v);
+                asm.emit(&i.mnemonic, &bytes, Some(asm.value(e)));
+            }
+            [Memory(w, rm), Immediate(e)] => {
+                let w = self.width(asm, i, *w, None);
+                let v = self.immediate(asm, i, e, w);
+                self.emit_modrm(asm, i, 0xC6 | w, 0, rm, &v);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    fn xchg(&self, asm: &mut Assembly, i: &Instruction, operands: &[Operand]) {
+        use Operand::*;
+
+        match operands {
+            [Register(1, 0), Register(1, r)] | [Register(1, r), Register(1, 0)] => {
+                asm.emit(&i.mnemonic, &[0x90 | r], None)
+            }
+            [d, Register(w, r)] | [Register(w, r), d] if self.rm(d).is_some() => {
+                let (dw, rm) = self.rm(d).unwrap();
+                let w = self.width(asm, i, dw, Some(*w));
+                self.emit_modrm(asm, i, 0x86 | w, *r, &rm, &[]);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // PUSH and POP, which only work on words
+    fn stack(&self, asm: &mut Assembly, i: &Instruction, pop: bool, operands: &[Operand]) {
+        let (register, segment, opcode, n) = if pop {
+            (0x58, 0x07, 0x8F, 0)
+        } else {
+            (0x50, 0x06, 0xFF, 6)
+        };
+        match operands {
+            [Operand::Register(1, r)] => asm.emit(&i.mnemonic, &[register | r], None),
+            [Operand::Segment(s)] => {
+                if *s == 1 && pop {
+                    asm.error("CS can't be popped");
+                }
+                asm.emit(&i.mnemonic, &[segment | s << 3], None);
+            }
+            [Operand::Memory(w, rm)] => {
+                self.width(asm, i, *w, Some(1));
+                self.emit_modrm(asm, i, opcode, n, rm, &[]);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // LEA, LDS and LES, which take the address of a memory operand
+    fn load_address(&self, asm: &mut Assembly, i: &Instruction, opcode: u8, operands: &[Operand]) {
+        match operands {
+            [Operand::Register(1, r), Operand::Memory(_, rm)] => {
+                self.emit_modrm(asm, i, opcode, *r, rm, &[])
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // REP and friends, alone or followed by a string instruction
+    fn repeat(&self, asm: &mut Assembly, i: &Instruction, prefix: u8, operands: &[Operand]) {
+        match operands {
+            [] => asm.emit(&i.mnemonic, &[prefix], None),
+            [Operand::Immediate(Expression::Identifier(s))] => match self.implied_opcode(s) {
+                Some(opcode) if (0xA4..=0xAF).contains(&opcode) => {
+                    asm.emit(&i.mnemonic, &[prefix, opcode], None)
+                }
+                _ => asm.error(&format!(
+                    "{} only applies to string instructions",
+                    i.mnemonic
+                )),
+            },
+            _ => asm.error(&format!(
+                "{} only applies to string instructions",
+                i.mnemonic
+            )),
+        }
+    }
+
+    // Destination of a jump, which has to be in the same 64K segment
+    fn destination(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> Option<i64> {
+        let d = asm.evaluate(e);
+        if let Some(d) = d {
+            asm.check(
+                (0..=0xFFFFF).contains(&d) && (d - i64::from(asm.address)).abs() <= 0xFFFF,
+                &format!("invalid address for {}", i.mnemonic),
+            );
+        }
+        d
+    }
+
+    // Jumps that only exist in the short form
+    fn short_jump(&self, asm: &mut Assembly, i: &Instruction, opcode: u8, operands: &[Operand]) {
+        match operands {
+            [Operand::Immediate(e) | Operand::Short(e)] => {
+                let d = self.destination(asm, i, e).unwrap_or(0);
+                let offset = d - i64::from(asm.address) - 2;
+                asm.check(
+                    (-128..=127).contains(&offset),
+                    &format!("jump out of range for {}", i.mnemonic),
+                );
+                asm.emit(&i.mnemonic, &[opcode, (offset & 255) as u8], Some(offset));
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // Whether a jump needs the near form, given the size of its short
+    // form, which is remembered from one pass to the next
+    fn relax(&mut self, asm: &Assembly, i: &Instruction, operand: &Operand) -> bool {
+        let e = match operand {
+            Operand::Short(_) => return false,
+            Operand::Near(_) => return true,
+            Operand::Immediate(e) => e,
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        };
+        if self.near.contains(&asm.line) {
+            return true;
+        }
+        if let Some(d) = self.destination(asm, i, e) {
+            if !(-128..=127).contains(&(d - i64::from(asm.address) - 2)) {
+                self.near.insert(asm.line);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Near relative jump or call, which wraps around the 64K segment
+    fn near_jump(&self, asm: &mut Assembly, i: &Instruction, opcodes: &[u8], e: &Expression) {
+        let size = opcodes.len() as i64 + 2;
+        let d = self.destination(asm, i, e).unwrap_or(0);
+        let offset = (d - i64::from(asm.address) - size) & 0xFFFF;
+        let mut bytes = opcodes.to_vec();
+        bytes.extend([(offset & 255) as u8, (offset >> 8) as u8]);
+        asm.emit(&i.mnemonic, &bytes, Some(d));
+    }
+
+    fn conditional_jump(
+        &mut self,
+        asm: &mut Assembly,
+        i: &Instruction,
+        opcode: u8,
+        operands: &[Operand],
+    ) {
+        let (o, e) = match operands {
+            [o @ (Operand::Immediate(e) | Operand::Short(e) | Operand::Near(e))] => (o, e),
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        };
+        if self.relax(asm, i, o) {
+            // The opposite condition skips over a near JMP
+            self.near_jump(asm, i, &[opcode ^ 1, 3, 0xE9], e);
+        } else {
+            self.short_jump(asm, i, opcode, &[Operand::Short(e)]);
+        }
+    }
+
+    fn jmp(&mut self, asm: &mut Assembly, i: &Instruction, operands: &[Operand]) {
+        use Operand::*;
+
+        match operands {
+            [o @ (Immediate(e) | Short(e) | Near(e))] => {
+                if self.relax(asm, i, o) {
+                    self.near_jump(asm, i, &[0xE9], e);
+                } else {
+                    self.short_jump(asm, i, 0xEB, &[Short(e)]);
+                }
+            }
+            [Far(s, o)] => self.far(asm, i, 0xEA, s, o),
+            [FarMemory(rm)] => self.emit_modrm(asm, i, 0xFF, 5, rm, &[]),
+            [d] if self.rm(d).is_some() => {
+                let (w, rm) = self.rm(d).unwrap();
+                self.width(asm, i, w, Some(1));
+                self.emit_modrm(asm, i, 0xFF, 4, &rm, &[]);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    fn call(&self, asm: &mut Assembly, i: &Instruction, operands: &[Operand]) {
+        use Operand::*;
+
+        match operands {
+            [Immediate(e) | Near(e)] => self.near_jump(asm, i, &[0xE8], e),
+            [Far(s, o)] => self.far(asm, i, 0x9A, s, o),
+            [FarMemory(rm)] => self.emit_modrm(asm, i, 0xFF, 3, rm, &[]),
+            [d] if self.rm(d).is_some() => {
+                let (w, rm) = self.rm(d).unwrap();
+                self.width(asm, i, w, Some(1));
+                self.emit_modrm(asm, i, 0xFF, 2, &rm, &[]);
+            }
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // Direct far jump or call, offset first
+    fn far(&self, asm: &mut Assembly, i: &Instruction, opcode: u8, s: &Expression, o: &Expression) {
+        let o = self.immediate(asm, i, o, 1);
+        let s = self.immediate(asm, i, s, 1);
+        asm.emit(
+            &i.mnemonic,
+            &[opcode, o[0], o[1], s[0], s[1]],
+            Some(i64::from(o[0]) | i64::from(o[1]) << 8),
+        );
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// iNES and NES 2.0 headers of NES ROM images, and their banks
+//
+// The ines.* directives describe the cartridge, and the 16-byte header
+// is written at the start of the image, outside of the address space.
+// The NES 2.0 format is used when a field doesn't fit in iNES, or when
+// a submapper or a RAM size is given.
+//
+// prg n, address and chr n, address start a bank, all the PRG banks
+// first, then the CHR banks, which follow each other in the image. The
+// size of the banks depends on the mapper. PRG banks are assembled at
+// $8000 by default, except for the fixed banks at the top of memory,
+// and CHR banks at 0 in the address space of the PPU.
+
+use crate::{Assembly, Expression, Instruction};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Rom {
+    Prg,
+    Chr,
+}
+
+pub struct InesHeader {
+    // PRG ROM size, in 16K units
+    prg: Option<i64>,
+    // CHR ROM size, in 8K units
+    chr: Option<i64>,
+    mapper: Option<i64>,
+    submapper: Option<i64>,
+    // Bits 0 and 3 of flags 6: vertical and four-screen
+    mirroring: Option<u8>,
+    battery: bool,
+    // RAM sizes, in bytes, NES 2.0 only
+    prg_ram: Option<i64>,
+    prg_nvram: Option<i64>,
+    chr_ram: Option<i64>,
+    chr_nvram: Option<i64>,
+    // Current bank, its number and its window in the address space
+    bank: Option<(Rom, u32, u32)>,
+}
+
+impl InesHeader {
+    pub fn new() -> InesHeader {
+        InesHeader {
+            prg: None,
+            chr: None,
+            mapper: None,
+            submapper: None,
+            mirroring: None,
+            battery: false,
+            prg_ram: None,
+            prg_nvram: None,
+            chr_ram: None,
+            chr_nvram: None,
+            bank: None,
+        }
+    }
+
+    // Handle the ines.* directives, and the prg and chr banks
+    pub fn directive(&mut self, asm: &mut Assembly, i: &Instruction, fill: u16) -> bool {
+        match i.mnemonic.as_str() {
+            "prg" => self.bank(asm, i, Rom::Prg, fill),
+            "chr" => self.bank(asm, i, Rom::Chr, fill),
+            "ines.prg" => Self::set(asm, i, &mut self.prg, 0, 0xEFF),
+            "ines.chr" => Self::set(asm, i, &mut self.chr, 0, 0xEFF),
+            "ines.mapper" => Self::set(asm, i, &mut self.mapper, 0, 4095),
+            "ines.submapper" => Self::set(asm, i, &mut self.submapper, 0, 15),
+            "ines.prgram" => Self::ram(asm, i, &mut self.prg_ram),
+            "ines.prgnvram" => Self::ram(asm, i, &mut self.prg_nvram),
+            "ines.chrram" => Self::ram(asm, i, &mut self.chr_ram),
+            "ines.chrnvram" => Self::ram(asm, i, &mut self.chr_nvram),
+            "ines.mirroring" => {
+                if self.mirroring.is_some() {
+                    asm.error("duplicate ines.mirroring");
+                }
+                self.mirroring = match asm.parameter(i) {
+                    Expression::Identifier(m) => match m.as_str() {
+                        "horizontal" => Some(0x00),
+                        "vertical" => Some(0x01),
+                        "four" => Some(0x08),
+                        _ => asm.error(&format!("unknown mirroring: {}", m)),
+                    },
+                    _ => asm.error("invalid parameter for ines.mirroring"),
+                };
+            }
+            "ines.battery" => {
+                asm.no_parameter(i);
+                self.battery = true;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn set(asm: &Assembly, i: &Instruction, field: &mut Option<i64>, min: i64, max: i64) {
+        if field.is_some() {
+            asm.error(&format!("duplicate {}", i.mnemonic));
+        }
+        let v = asm.value(asm.parameter(i));
+        asm.check(
+            (min..=max).contains(&v),
+            &format!("invalid parameter value for {}", i.mnemonic),
+        );
+        *field = Some(v);
+    }
+
+    // NES 2.0 RAM sizes are 64 bytes shifted left by a 4-bit count,
+    // where 0 means no RAM
+    fn ram(asm: &Assembly, i: &Instruction, field: &mut Option<i64>) {
+        Self::set(asm, i, field, 0, 64 << 15);
+        let size = field.unwrap();
+        if size != 0 && (size < 128 || size.count_ones() != 1) {
+            asm.error(&format!("invalid RAM size for {}", i.mnemonic));
+        }
+    }
+
+    // Size and number of the banks of a ROM, which depend on the mapper
+    fn banks(&self, asm: &Assembly, rom: Rom) -> (u32, u32) {
+        let (size, total) = match rom {
+            Rom::Prg => (
+                match self.mapper {
+                    Some(4) => 8192,
+                    Some(7) => 32768,
+                    _ => 16384,
+                },
+                self.prg.map(|p| p * 16384),
+            ),
+            Rom::Chr => (
+                match self.mapper {
+                    Some(1) => 4096,
+                    Some(4) => 1024,
+                    _ => 8192,
+                },
+                self.chr.map(|c| c * 8192),
+            ),
+        };
+        match total {
+            Some(total) => (size, (total / i64::from(size)) as u32),
+            None => asm.error("banks need ines.prg and ines.chr"),
+        }
+    }
+
+    fn bank(&mut self, asm: &mut Assembly, i: &Instruction, rom: Rom, fill: u16) {
+        let (size, count) = self.banks(asm, rom);
+        let expected = match self.bank {
+            Some((r, n, _)) if r == rom => n + 1,
+            Some((Rom::Chr, _, _)) => asm.error("prg bank after chr banks"),
+            Some((Rom::Prg, n, _)) => {
+                let (_, prg) = self.banks(asm, Rom::Prg);
+                asm.check(n + 1 == prg, &format!("{} prg banks needed", prg));
+                0
+            }
+            None => 0,
+        };
+        // The last PRG banks are fixed at the top of memory
+        let default = match rom {
+            Rom::Chr => 0,
+            Rom::Prg if expected + 1 == count => 0x10000 - size,
+            Rom::Prg if size == 8192 && expected + 2 == count => 0xC000,
+            Rom::Prg => 0x8000,
+        };
+        let (n, window) = match i.parameters.as_slice() {
+            [n] => (asm.value(n), i64::from(default)),
+            [n, w] => (asm.value(n), asm.value(w)),
+            _ => asm.error(&format!("invalid parameters for {}", i.mnemonic)),
+        };
+        if n != i64::from(expected) {
+            asm.error(&format!("expected {} bank {}", i.mnemonic, expected));
+        }
+        if expected >= count {
+            asm.error(&format!("too many {} banks", i.mnemonic));
+        }
+        if !(0..=0x10000 - i64::from(size)).contains(&window) {
+            asm.error(&format!("invalid window for {} bank", i.mnemonic));
+        }
+        self.close(asm, fill);
+        if asm.address == 0 {
+            asm.org(window as u32, fill);
+        } else {
+            asm.rorg(window as u32);
+        }
+        self.bank = Some((rom, expected, window as u32));
+        asm.bank = Some(i64::from(expected));
+    }
+
+    // Pad the current bank to its full size, CHR banks with zeros
+    fn close(&mut self, asm: &mut Assembly, fill: u16) {
+        let (rom, n, window) = match self.bank {
+            Some(b) => b,
+            None => return,
+        };
+        let (size, _) = self.banks(asm, rom);
+        let end = window + size;
+        let (name, fill) = match rom {
+            Rom::Prg => ("prg", fill),
+            Rom::Chr => ("chr", 0),
+        };
+        if asm.address > end {
+            asm.check(false, &format!("{} bank {} overflows", name, n));
+            return;
+        }
+        asm.pad(end, fill);
+    }
+
+    // Close the last bank, and check that none is missing and that
+    // the image has the size that the header gives
+    pub fn end_pass(&mut self, asm: &mut Assembly, fill: u16) {
+        if let Some((rom, n, _)) = self.bank {
+            self.close(asm, fill);
+            let (_, count) = self.banks(asm, rom);
+            let (name, chr) = match rom {
+                Rom::Prg => ("prg", self.banks(asm, Rom::Chr).1),
+                Rom::Chr => ("chr", 0),
+            };
+            asm.check(n + 1 == count, &format!("{} {} banks needed", count, name));
+            asm.check(chr == 0, &format!("{} chr banks needed", chr));
+        }
+        if self.used() && self.prg.is_none() {
+            asm.error("missing ines.prg");
+        }
+        if let Some(prg) = self.prg {
+            let size = prg * 16384 + self.chr.unwrap_or(0) * 8192;
+            asm.check(
+                asm.image.len() as i64 == size,
+                &format!(
+                    "image of {} bytes, ines.prg and ines.chr need {}",
+                    asm.image.len(),
+                    size
+                ),
+            );
+        }
+    }
+
+    // Whether any ines.* directive was used
+    fn used(&self) -> bool {
+        self.prg.is_some()
+            || self.chr.is_some()
+            || self.mapper.is_some()
+            || self.submapper.is_some()
+            || self.mirroring.is_some()
+            || self.battery
+            || [self.prg_ram, self.prg_nvram, self.chr_ram, self.chr_nvram]
+                .iter()
+                .any(|r| r.is_some())
+    }
+
+    // The header, or nothing if no ines.* directive was used
+    pub fn bytes(&self) -> Vec<u8> {
+        if !self.used() {
+            return vec![];
+        }
+        let ram = [self.prg_ram, self.prg_nvram, self.chr_ram, self.chr_nvram];
+        let prg = self.prg.unwrap();
+        let chr = self.chr.unwrap_or(0);
+        let mapper = self.mapper.unwrap_or(0);
+        let nes2 = prg > 255
+            || chr > 255
+            || mapper > 255
+            || self.submapper.is_some()
+            || ram.iter().any(|r| r.is_some());
+
+        let mut header = vec![b'N', b'E', b'S', 0x1A, (prg & 255) as u8, (chr & 255) as u8];
+        header.push(
+            ((mapper & 0x0F) << 4) as u8
+                | self.mirroring.unwrap_or(0)
+                | if self.battery { 0x02 } else { 0x00 },
+        );
+        header.push((mapper & 0xF0) as u8 | if nes2 { 0x08 } else { 0x00 });
+        if nes2 {
+            header.push((mapper >> 8) as u8 | (self.submapper.unwrap_or(0) << 4) as u8);
+            header.push((prg >> 8) as u8 | ((chr >> 8) << 4) as u8);
+            header.push(Self::shift(self.prg_ram) | Self::shift(self.prg_nvram) << 4);
+            header.push(Self::shift(self.chr_ram) | Self::shift(self.chr_nvram) << 4);
+        }
+        header.resize(16, 0);
+        header
+    }
+
+    // Shift count of a RAM size, checked by ram()
+    fn shift(size: Option<i64>) -> u8 {
+        match size {
+            None | Some(0) => 0,
+            Some(size) => (size.trailing_zeros() - 6) as u8,
+        }
+    }
+}
@@ -0,0 +1,1436 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// MOS 6502 back end, and its CMOS descendants
+//
+// The addressing mode comes from the syntax of the operands: #value,
+// address, address,X, address,Y, (address), (address,X), (address),Y,
+// and A or nothing for the accumulator. Addresses use the shortest
+// form that fits their value, with unknown addresses taking 16 bits.
+//
+// The 65816 adds [address] and [address],Y for 24-bit indirect
+// addresses, offset,S and (offset,S),Y for stack-relative addressing,
+// and MVN/MVP source,destination for block moves. Its immediate values
+// are 8 or 16 bits depending on the M and X flags, which are tracked in
+// source order through the a8, a16, i8 and i16 directives and through
+// REP and SEP.
+//
+// Outside of the 65816, the listing gives the cycles of each instruction
+// on the NMOS 6502, with the extra cycles for taken branches and for
+// indexing across a page when they can happen. cycles_begin and
+// cycles_end n report the fewest and most cycles that the instructions
+// between them take, in source order, and check that it's at most n.
+
+use crate::ines::InesHeader;
+use crate::simulator;
+use crate::vcs::Bankswitch;
+use crate::{Assembly, Backend, Expression, Instruction};
+
+// Members of the 6502 family, each adding opcodes to the previous ones
+#[derive(Clone, Copy, PartialEq)]
+pub enum Variant {
+    // The original NMOS 6502
+    Nmos,
+    // NMOS 6502 with its undocumented opcodes
+    Undocumented,
+    // Ricoh 2A03 of the NES, which has no decimal mode
+    Ricoh,
+    // 6507 of the Atari 2600, which only has 13 address lines
+    M6507,
+    // 65C02, as in the Apple IIc and enhanced IIe
+    Cmos,
+    // Rockwell R65C02, which adds bit manipulation instructions
+    Rockwell,
+    // WDC 65816, as in the SNES and the Apple IIgs
+    W65816,
+}
+
+pub struct Mos6502Assembler {
+    variant: Variant,
+    // Whether the accumulator and the index registers are 16-bit wide,
+    // on the 65816
+    accumulator16: bool,
+    index16: bool,
+    // Whether SED was used, on the 2A03
+    decimal: bool,
+    // Whether addresses are folded into the 8K window, on the 6507
+    fold: bool,
+    // iNES header, on the 2A03
+    ines: InesHeader,
+    // Bank switching, on the 6507
+    banks: Bankswitch,
+    // Open cycles_begin regions: line, fewest and most cycles so far
+    regions: Vec<(u32, u32, u32)>,
+}
+
+impl Mos6502Assembler {
+    pub fn new(variant: Variant) -> Mos6502Assembler {
+        Mos6502Assembler {
+            variant,
+            accumulator16: false,
+            index16: false,
+            decimal: false,
+            fold: false,
+            ines: InesHeader::new(),
+            banks: Bankswitch::new(),
+            regions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    // JMP (address)
+    Indirect,
+    // (zp,X)
+    IndirectX,
+    // (zp),Y
+    IndirectY,
+    // (zp), 65C02 only
+    ZeroPageIndirect,
+    // JMP (address,X), 65C02 only
+    AbsoluteIndirectX,
+    Relative,
+    // BBR and BBS, a zero page address and a branch
+    ZeroPageRelative,
+    // 65816 only from here
+    AbsoluteLong,
+    AbsoluteLongX,
+    // [zp]
+    ZeroPageIndirectLong,
+    // [zp],Y
+    ZeroPageIndirectLongY,
+    // JML [address]
+    AbsoluteIndirectLong,
+    // offset,S
+    StackRelative,
+    // (offset,S),Y
+    StackRelativeIndirectY,
+    // BRL and PER
+    RelativeLong,
+    // MVN and MVP, with a source and a destination bank
+    BlockMove,
+}
+
+// Opcodes of the original 6502
+const NMOS_OPCODES: [(&str, Mode, u8); 151] = [
+    ("ADC", Mode::Immediate, 0x69),
+    ("ADC", Mode::ZeroPage, 0x65),
+    ("ADC", Mode::ZeroPageX, 0x75),
+    ("ADC", Mode::Absolute, 0x6D),
+    ("ADC", Mode::AbsoluteX, 0x7D),
+    ("ADC", Mode::AbsoluteY, 0x79),
+    ("ADC", Mode::IndirectX, 0x61),
+    ("ADC", Mode::IndirectY, 0x71),
+    ("AND", Mode::Immediate, 0x29),
+    ("AND", Mode::ZeroPage, 0x25),
+    ("AND", Mode::ZeroPageX, 0x35),
+    ("AND", Mode::Absolute, 0x2D),
+    ("AND", Mode::AbsoluteX, 0x3D),
+    ("AND", Mode::AbsoluteY, 0x39),
+    ("AND", Mode::IndirectX, 0x21),
+    ("AND", Mode::IndirectY, 0x31),
+    ("ASL", Mode::Accumulator, 0x0A),
+    ("ASL", Mode::ZeroPage, 0x06),
+    ("ASL", Mode::ZeroPageX, 0x16),
+    ("ASL", Mode::Absolute, 0x0E),
+    ("ASL", Mode::AbsoluteX, 0x1E),
+    ("BCC", Mode::Relative, 0x90),
+    ("BCS", Mode::Relative, 0xB0),
+    ("BEQ", Mode::Relative, 0xF0),
+    ("BIT", Mode::ZeroPage, 0x24),
+    ("BIT", Mode::Absolute, 0x2C),
+    ("BMI", Mode::Relative, 0x30),
+    ("BNE", Mode::Relative, 0xD0),
+    ("BPL", Mode::Relative, 0x10),
+    ("BRK", Mode::Implied, 0x00),
+    ("BVC", Mode::Relative, 0x50),
+    ("BVS", Mode::Relative, 0x70),
+    ("CLC", Mode::Implied, 0x18),
+    ("CLD", Mode::Implied, 0xD8),
+    ("CLI", Mode::Implied, 0x58),
+    ("CLV", Mode::Implied, 0xB8),
+    ("CMP", Mode::Immediate, 0xC9),
+    ("CMP", Mode::ZeroPage, 0xC5),
+    ("CMP", Mode::ZeroPageX, 0xD5),
+    ("CMP", Mode::Absolute, 0xCD),
+    ("CMP", Mode::AbsoluteX, 0xDD),
+    ("CMP", Mode::AbsoluteY, 0xD9),
+    ("CMP", Mode::IndirectX, 0xC1),
+    ("CMP", Mode::IndirectY, 0xD1),
+    ("CPX", Mode::Immediate, 0xE0),
+    ("CPX", Mode::ZeroPage, 0xE4),
+    ("CPX", Mode::Absolute, 0xEC),
+    ("CPY", Mode::Immediate, 0xC0),
+    ("CPY", Mode::ZeroPage, 0xC4),
+    ("CPY", Mode::Absolute, 0xCC),
+    ("DEC", Mode::ZeroPage, 0xC6),
+    ("DEC", Mode::ZeroPageX, 0xD6),
+    ("DEC", Mode::Absolute, 0xCE),
+    ("DEC", Mode::AbsoluteX, 0xDE),
+    ("DEX", Mode::Implied, 0xCA),
+    ("DEY", Mode::Implied, 0x88),
+    ("EOR", Mode::Immediate, 0x49),
+    ("EOR", Mode::ZeroPage, 0x45),
+    ("EOR", Mode::ZeroPageX, 0x55),
+    ("EOR", Mode::Absolute, 0x4D),
+    ("EOR", Mode::AbsoluteX, 0x5D),
+    ("EOR", Mode::AbsoluteY, 0x59),
+    ("EOR", Mode::IndirectX, 0x41),
+    ("EOR", Mode::IndirectY, 0x51),
+    ("INC", Mode::ZeroPage, 0xE6),
+    ("INC", Mode::ZeroPageX, 0xF6),
+    ("INC", Mode::Absolute, 0xEE),
+    ("INC", Mode::AbsoluteX, 0xFE),
+    ("INX", Mode::Implied, 0xE8),
+    ("INY", Mode::Implied, 0xC8),
+    ("JMP", Mode::Absolute, 0x4C),
+    ("JMP", Mode::Indirect, 0x6C),
+    ("JSR", Mode::Absolute, 0x20),
+    ("LDA", Mode::Immediate, 0xA9),
+    ("LDA", Mode::ZeroPage, 0xA5),
+    ("LDA", Mode::ZeroPageX, 0xB5),
+    ("LDA", Mode::Absolute, 0xAD),
+    ("LDA", Mode::AbsoluteX, 0xBD),
+    ("LDA", Mode::AbsoluteY, 0xB9),
+    ("LDA", Mode::IndirectX, 0xA1),
+    ("LDA", Mode::IndirectY, 0xB1),
+    ("LDX", Mode::Immediate, 0xA2),
+    ("LDX", Mode::ZeroPage, 0xA6),
+    ("LDX", Mode::ZeroPageY, 0xB6),
+    ("LDX", Mode::Absolute, 0xAE),
+    ("LDX", Mode::AbsoluteY, 0xBE),
+    ("LDY", Mode::Immediate, 0xA0),
+    ("LDY", Mode::ZeroPage, 0xA4),
+    ("LDY", Mode::ZeroPageX, 0xB4),
+    ("LDY", Mode::Absolute, 0xAC),
+    ("LDY", Mode::AbsoluteX, 0xBC),
+    ("LSR", Mode::Accumulator, 0x4A),
+    ("LSR", Mode::ZeroPage, 0x46),
+    ("LSR", Mode::ZeroPageX, 0x56),
+    ("LSR", Mode::Absolute, 0x4E),
+    ("LSR", Mode::AbsoluteX, 0x5E),
+    ("NOP", Mode::Implied, 0xEA),
+    ("ORA", Mode::Immediate, 0x09),
+    ("ORA", Mode::ZeroPage, 0x05),
+    ("ORA", Mode::ZeroPageX, 0x15),
+    ("ORA", Mode::Absolute, 0x0D),
+    ("ORA", Mode::AbsoluteX, 0x1D),
+    ("ORA", Mode::AbsoluteY, 0x19),
+    ("ORA", Mode::IndirectX, 0x01),
+    ("ORA", Mode::IndirectY, 0x11),
+    ("PHA", Mode::Implied, 0x48),
+    ("PHP", Mode::Implied, 0x08),
+    ("PLA", Mode::Implied, 0x68),
+    ("PLP", Mode::Implied, 0x28),
+    ("ROL", Mode::Accumulator, 0x2A),
+    ("ROL", Mode::ZeroPage, 0x26),
+    ("ROL", Mode::ZeroPageX, 0x36),
+    ("ROL", Mode::Absolute, 0x2E),
+    ("ROL", Mode::AbsoluteX, 0x3E),
+    ("ROR", Mode::Accumulator, 0x6A),
+    ("ROR", Mode::ZeroPage, 0x66),
+    ("ROR", Mode::ZeroPageX, 0x76),
+    ("ROR", Mode::Absolute, 0x6E),
+    ("ROR", Mode::AbsoluteX, 0x7E),
+    ("RTI", Mode::Implied, 0x40),
+    ("RTS", Mode::Implied, 0x60),
+    ("SBC", Mode::Immediate, 0xE9),
+    ("SBC", Mode::ZeroPage, 0xE5),
+    ("SBC", Mode::ZeroPageX, 0xF5),
+    ("SBC", Mode::Absolute, 0xED),
+    ("SBC", Mode::AbsoluteX, 0xFD),
+    ("SBC", Mode::AbsoluteY, 0xF9),
+    ("SBC", Mode::IndirectX, 0xE1),
+    ("SBC", Mode::IndirectY, 0xF1),
+    ("SEC", Mode::Implied, 0x38),
+    ("SED", Mode::Implied, 0xF8),
+    ("SEI", Mode::Implied, 0x78),
+    ("STA", Mode::ZeroPage, 0x85),
+    ("STA", Mode::ZeroPageX, 0x95),
+    ("STA", Mode::Absolute, 0x8D),
+    ("STA", Mode::AbsoluteX, 0x9D),
+    ("STA", Mode::AbsoluteY, 0x99),
+    ("STA", Mode::IndirectX, 0x81),
+    ("STA", Mode::IndirectY, 0x91),
+    ("STX", Mode::ZeroPage, 0x86),
+    ("STX", Mode::ZeroPageY, 0x96),
+    ("STX", Mode::Absolute, 0x8E),
+    ("STY", Mode::ZeroPage, 0x84),
+    ("STY", Mode::ZeroPageX, 0x94),
+    ("STY", Mode::Absolute, 0x8C),
+    ("TAX", Mode::Implied, 0xAA),
+    ("TAY", Mode::Implied, 0xA8),
+    ("TSX", Mode::Implied, 0xBA),
+    ("TXA", Mode::Implied, 0x8A),
+    ("TXS", Mode::Implied, 0x9A),
+    ("TYA", Mode::Implied, 0x98),
+];
+
+// Undocumented opcodes of the NMOS 6502, which combine two
+// instructions, or are NOPs that read their operands
+const UNDOCUMENTED_OPCODES: [(&str, Mode, u8); 62] = [
+    ("SLO", Mode::ZeroPage, 0x07),
+    ("SLO", Mode::ZeroPageX, 0x17),
+    ("SLO", Mode::Absolute, 0x0F),
+    ("SLO", Mode::AbsoluteX, 0x1F),
+    ("SLO", Mode::AbsoluteY, 0x1B),
+    ("SLO", Mode::IndirectX, 0x03),
+    ("SLO", Mode::IndirectY, 0x13),
+    ("RLA", Mode::ZeroPage, 0x27),
+    ("RLA", Mode::ZeroPageX, 0x37),
+    ("RLA", Mode::Absolute, 0x2F),
+    ("RLA", Mode::AbsoluteX, 0x3F),
+    ("RLA", Mode::AbsoluteY, 0x3B),
+    ("RLA", Mode::IndirectX, 0x23),
+    ("RLA", Mode::IndirectY, 0x33),
+    ("SRE", Mode::ZeroPage, 0x47),
+    ("SRE", Mode::ZeroPageX, 0x57),
+    ("SRE", Mode::Absolute, 0x4F),
+    ("SRE", Mode::AbsoluteX, 0x5F),
+    ("SRE", Mode::AbsoluteY, 0x5B),
+    ("SRE", Mode::IndirectX, 0x43),
+    ("SRE", Mode::IndirectY, 0x53),
+    ("RRA", Mode::ZeroPage, 0x67),
+    ("RRA", Mode::ZeroPageX, 0x77),
+    ("RRA", Mode::Absolute, 0x6F),
+    ("RRA", Mode::AbsoluteX, 0x7F),
+    ("RRA", Mode::AbsoluteY, 0x7B),
+    ("RRA", Mode::IndirectX, 0x63),
+    ("RRA", Mode::IndirectY, 0x73),
+    ("DCP", Mode::ZeroPage, 0xC7),
+    ("DCP", Mode::ZeroPageX, 0xD7),
+    ("DCP", Mode::Absolute, 0xCF),
+    ("DCP", Mode::AbsoluteX, 0xDF),
+    ("DCP", Mode::AbsoluteY, 0xDB),
+    ("DCP", Mode::IndirectX, 0xC3),
+    ("DCP", Mode::IndirectY, 0xD3),
+    ("ISC", Mode::ZeroPage, 0xE7),
+    ("ISC", Mode::ZeroPageX, 0xF7),
+    ("ISC", Mode::Absolute, 0xEF),
+    ("ISC", Mode::AbsoluteX, 0xFF),
+    ("ISC", Mode::AbsoluteY, 0xFB),
+    ("ISC", Mode::IndirectX, 0xE3),
+    ("ISC", Mode::IndirectY, 0xF3),
+    ("SAX", Mode::ZeroPage, 0x87),
+    ("SAX", Mode::ZeroPageY, 0x97),
+    ("SAX", Mode::Absolute, 0x8F),
+    ("SAX", Mode::IndirectX, 0x83),
+    ("LAX", Mode::ZeroPage, 0xA7),
+    ("LAX", Mode::ZeroPageY, 0xB7),
+    ("LAX", Mode::Absolute, 0xAF),
+    ("LAX", Mode::AbsoluteY, 0xBF),
+    ("LAX", Mode::IndirectX, 0xA3),
+    ("LAX", Mode::IndirectY, 0xB3),
+    ("ANC", Mode::Immediate, 0x0B),
+    ("ALR", Mode::Immediate, 0x4B),
+    ("ARR", Mode::Immediate, 0x6B),
+    ("SBX", Mode::Immediate, 0xCB),
+    ("LAS", Mode::AbsoluteY, 0xBB),
+    ("NOP", Mode::Immediate, 0x80),
+    ("NOP", Mode::ZeroPage, 0x04),
+    ("NOP", Mode::ZeroPageX, 0x14),
+    ("NOP", Mode::Absolute, 0x0C),
+    ("NOP", Mode::AbsoluteX, 0x1C),
+];
+
+// Undocumented opcodes whose results depend on the chip or on the
+// value floating on the bus
+const UNSTABLE_OPCODES: [(&str, Mode, u8); 7] = [
+    ("XAA", Mode::Immediate, 0x8B),
+    ("LAX", Mode::Immediate, 0xAB),
+    ("AHX", Mode::AbsoluteY, 0x9F),
+    ("AHX", Mode::IndirectY, 0x93),
+    ("TAS", Mode::AbsoluteY, 0x9B),
+    ("SHY", Mode::AbsoluteX, 0x9C),
+    ("SHX", Mode::AbsoluteY, 0x9E),
+];
+
+// PPU and APU registers of the NES
+// Whether an opcode is one of the unstable undocumented ones
+pub fn unstable(opcode: u8) -> bool {
+    UNSTABLE_OPCODES.iter().any(|(_, _, o)| *o == opcode)
+}
+
+const NES_SYMBOLS: [(&str, i64); 30] = [
+    ("PPUCTRL", 0x2000),
+    ("PPUMASK", 0x2001),
+    ("PPUSTATUS", 0x2002),
+    ("OAMADDR", 0x2003),
+    ("OAMDATA", 0x2004),
+    ("PPUSCROLL", 0x2005),
+    ("PPUADDR", 0x2006),
+    ("PPUDATA", 0x2007),
+    ("SQ1_VOL", 0x4000),
+    ("SQ1_SWEEP", 0x4001),
+    ("SQ1_LO", 0x4002),
+    ("SQ1_HI", 0x4003),
+    ("SQ2_VOL", 0x4004),
+    ("SQ2_SWEEP", 0x4005),
+    ("SQ2_LO", 0x4006),
+    ("SQ2_HI", 0x4007),
+    ("TRI_LINEAR", 0x4008),
+    ("TRI_LO", 0x400A),
+    ("TRI_HI", 0x400B),
+    ("NOISE_VOL", 0x400C),
+    ("NOISE_LO", 0x400E),
+    ("NOISE_HI", 0x400F),
+    ("DMC_FREQ", 0x4010),
+    ("DMC_RAW", 0x4011),
+    ("DMC_START", 0x4012),
+    ("DMC_LEN", 0x4013),
+    ("OAMDMA", 0x4014),
+    ("SND_CHN", 0x4015),
+    ("JOY1", 0x4016),
+    ("JOY2", 0x4017),
+];
+
+// TIA registers, written then read, and RIOT registers of the
+// Atari 2600
+const VCS_SYMBOLS: [(&str, i64); 69] = [
+    ("VSYNC", 0x00),
+    ("VBLANK", 0x01),
+    ("WSYNC", 0x02),
+    ("RSYNC", 0x03),
+    ("NUSIZ0", 0x04),
+    ("NUSIZ1", 0x05),
+    ("COLUP0", 0x06),
+    ("COLUP1", 0x07),
+    ("COLUPF", 0x08),
+    ("COLUBK", 0x09),
+    ("CTRLPF", 0x0A),
+    ("REFP0", 0x0B),
+    ("REFP1", 0x0C),
+    ("PF0", 0x0D),
+    ("PF1", 0x0E),
+    ("PF2", 0x0F),
+    ("RESP0", 0x10),
+    ("RESP1", 0x11),
+    ("RESM0", 0x12),
+    ("RESM1", 0x13),
+    ("RESBL", 0x14),
+    ("AUDC0", 0x15),
+    ("AUDC1", 0x16),
+    ("AUDF0", 0x17),
+    ("AUDF1", 0x18),
+    ("AUDV0", 0x19),
+    ("AUDV1", 0x1A),
+    ("GRP0", 0x1B),
+    ("GRP1", 0x1C),
+    ("ENAM0", 0x1D),
+    ("ENAM1", 0x1E),
+    ("ENABL", 0x1F),
+    ("HMP0", 0x20),
+    ("HMP1", 0x21),
+    ("HMM0", 0x22),
+    ("HMM1", 0x23),
+    ("HMBL", 0x24),
+    ("VDELP0", 0x25),
+    ("VDELP1", 0x26),
+    ("VDELBL", 0x27),
+    ("RESMP0", 0x28),
+    ("RESMP1", 0x29),
+    ("HMOVE", 0x2A),
+    ("HMCLR", 0x2B),
+    ("CXCLR", 0x2C),
+    ("CXM0P", 0x00),
+    ("CXM1P", 0x01),
+    ("CXP0FB", 0x02),
+    ("CXP1FB", 0x03),
+    ("CXM0FB", 0x04),
+    ("CXM1FB", 0x05),
+    ("CXBLPF", 0x06),
+    ("CXPPMM", 0x07),
+    ("INPT0", 0x08),
+    ("INPT1", 0x09),
+    ("INPT2", 0x0A),
+    ("INPT3", 0x0B),
+    ("INPT4", 0x0C),
+    ("INPT5", 0x0D),
+    ("SWCHA", 0x280),
+    ("SWACNT", 0x281),
+    ("SWCHB", 0x282),
+    ("SWBCNT", 0x283),
+    ("INTIM", 0x284),
+    ("TIMINT", 0x285),
+    ("TIM1T", 0x294),
+    ("TIM8T", 0x295),
+    ("TIM64T", 0x296),
+    ("T1024T", 0x297),
+];
+
+// Opcodes added by the 65C02
+const CMOS_OPCODES: [(&str, Mode, u8); 27] = [
+    ("ADC", Mode::ZeroPageIndirect, 0x72),
+    ("AND", Mode::ZeroPageIndirect, 0x32),
+    ("BIT", Mode::Immediate, 0x89),
+    ("BIT", Mode::ZeroPageX, 0x34),
+    ("BIT", Mode::AbsoluteX, 0x3C),
+    ("BRA", Mode::Relative, 0x80),
+    ("CMP", Mode::ZeroPageIndirect, 0xD2),
+    ("DEC", Mode::Accumulator, 0x3A),
+    ("EOR", Mode::ZeroPageIndirect, 0x52),
+    ("INC", Mode::Accumulator, 0x1A),
+    ("JMP", Mode::AbsoluteIndirectX, 0x7C),
+    ("LDA", Mode::ZeroPageIndirect, 0xB2),
+    ("ORA", Mode::ZeroPageIndirect, 0x12),
+    ("PHX", Mode::Implied, 0xDA),
+    ("PHY", Mode::Implied, 0x5A),
+    ("PLX", Mode::Implied, 0xFA),
+    ("PLY", Mode::Implied, 0x7A),
+    ("SBC", Mode::ZeroPageIndirect, 0xF2),
+    ("STA", Mode::ZeroPageIndirect, 0x92),
+    ("STZ", Mode::ZeroPage, 0x64),
+    ("STZ", Mode::ZeroPageX, 0x74),
+    ("STZ", Mode::Absolute, 0x9C),
+    ("STZ", Mode::AbsoluteX, 0x9E),
+    ("TRB", Mode::ZeroPage, 0x14),
+    ("TRB", Mode::Absolute, 0x1C),
+    ("TSB", Mode::ZeroPage, 0x04),
+    ("TSB", Mode::Absolute, 0x0C),
+];
+
+// Opcodes added by the Rockwell R65C02, with the bit number
+// in the mnemonic and in the high bits of the opcode
+const ROCKWELL_OPCODES: [(&str, Mode, u8); 32] = [
+    ("RMB0", Mode::ZeroPage, 0x07),
+    ("RMB1", Mode::ZeroPage, 0x17),
+    ("RMB2", Mode::ZeroPage, 0x27),
+    ("RMB3", Mode::ZeroPage, 0x37),
+    ("RMB4", Mode::ZeroPage, 0x47),
+    ("RMB5", Mode::ZeroPage, 0x57),
+    ("RMB6", Mode::ZeroPage, 0x67),
+    ("RMB7", Mode::ZeroPage, 0x77),
+    ("SMB0", Mode::ZeroPage, 0x87),
+    ("SMB1", Mode::ZeroPage, 0x97),
+    ("SMB2", Mode::ZeroPage, 0xA7),
+    ("SMB3", Mode::ZeroPage, 0xB7),
+    ("SMB4", Mode::ZeroPage, 0xC7),
+    ("SMB5", Mode::ZeroPage, 0xD7),
+    ("SMB6", Mode::ZeroPage, 0xE7),
+    ("SMB7", Mode::ZeroPage, 0xF7),
+    ("BBR0", Mode::ZeroPageRelative, 0x0F),
+    ("BBR1", Mode::ZeroPageRelative, 0x1F),
+    ("BBR2", Mode::ZeroPageRelative, 0x2F),
+    ("BBR3", Mode::ZeroPageRelative, 0x3F),
+    ("BBR4", Mode::ZeroPageRelative, 0x4F),
+    ("BBR5", Mode::ZeroPageRelative, 0x5F),
+    ("BBR6", Mode::ZeroPageRelative, 0x6F),
+    ("BBR7", Mode::ZeroPageRelative, 0x7F),
+    ("BBS0", Mode::ZeroPageRelative, 0x8F),
+    ("BBS1", Mode::ZeroPageRelative, 0x9F),
+    ("BBS2", Mode::ZeroPageRelative, 0xAF),
+    ("BBS3", Mode::ZeroPageRelative, 0xBF),
+    ("BBS4", Mode::ZeroPageRelative, 0xCF),
+    ("BBS5", Mode::ZeroPageRelative, 0xDF),
+    ("BBS6", Mode::ZeroPageRelative, 0xEF),
+    ("BBS7", Mode::ZeroPageRelative, 0xFF),
+];
+
+// Opcodes added by the 65816
+const W65816_OPCODES: [(&str, Mode, u8); 78] = [
+    ("ADC", Mode::StackRelative, 0x63),
+    ("ADC", Mode::ZeroPageIndirectLong, 0x67),
+    ("ADC", Mode::AbsoluteLong, 0x6F),
+    ("ADC", Mode::StackRelativeIndirectY, 0x73),
+    ("ADC", Mode::ZeroPageIndirectLongY, 0x77),
+    ("ADC", Mode::AbsoluteLongX, 0x7F),
+    ("AND", Mode::StackRelative, 0x23),
+    ("AND", Mode::ZeroPageIndirectLong, 0x27),
+    ("AND", Mode::AbsoluteLong, 0x2F),
+    ("AND", Mode::StackRelativeIndirectY, 0x33),
+    ("AND", Mode::ZeroPageIndirectLongY, 0x37),
+    ("AND", Mode::AbsoluteLongX, 0x3F),
+    ("BRL", Mode::RelativeLong, 0x82),
+    ("CMP", Mode::StackRelative, 0xC3),
+    ("CMP", Mode::ZeroPageIndirectLong, 0xC7),
+    ("CMP", Mode::AbsoluteLong, 0xCF),
+    ("CMP", Mode::StackRelativeIndirectY, 0xD3),
+    ("CMP", Mode::ZeroPageIndirectLongY, 0xD7),
+    ("CMP", Mode::AbsoluteLongX, 0xDF),
+    ("COP", Mode::Immediate, 0x02),
+    ("EOR", Mode::StackRelative, 0x43),
+    ("EOR", Mode::ZeroPageIndirectLong, 0x47),
+    ("EOR", Mode::AbsoluteLong, 0x4F),
+    ("EOR", Mode::StackRelativeIndirectY, 0x53),
+    ("EOR", Mode::ZeroPageIndirectLongY, 0x57),
+    ("EOR", Mode::AbsoluteLongX, 0x5F),
+    ("JML", Mode::AbsoluteLong, 0x5C),
+    ("JML", Mode::AbsoluteIndirectLong, 0xDC),
+    ("JSL", Mode::AbsoluteLong, 0x22),
+    ("JSR", Mode::AbsoluteIndirectX, 0xFC),
+    ("LDA", Mode::StackRelative, 0xA3),
+    ("LDA", Mode::ZeroPageIndirectLong, 0xA7),
+    ("LDA", Mode::AbsoluteLong, 0xAF),
+    ("LDA", Mode::StackRelativeIndirectY, 0xB3),
+    ("LDA", Mode::ZeroPageIndirectLongY, 0xB7),
+    ("LDA", Mode::AbsoluteLongX, 0xBF),
+    ("MVN", Mode::BlockMove, 0x54),
+    ("MVP", Mode::BlockMove, 0x44),
+    ("ORA", Mode::StackRelative, 0x03),
+    ("ORA", Mode::ZeroPageIndirectLong, 0x07),
+    ("ORA", Mode::AbsoluteLong, 0x0F),
+    ("ORA", Mode::StackRelativeIndirectY, 0x13),
+    ("ORA", Mode::ZeroPageIndirectLongY, 0x17),
+    ("ORA", Mode::AbsoluteLongX, 0x1F),
+    ("PEA", Mode::Absolute, 0xF4),
+    ("PEI", Mode::ZeroPageIndirect, 0xD4),
+    ("PER", Mode::RelativeLong, 0x62),
+    ("PHB", Mode::Implied, 0x8B),
+    ("PHD", Mode::Implied, 0x0B),
+    ("PHK", Mode::Implied, 0x4B),
+    ("PLB", Mode::Implied, 0xAB),
+    ("PLD", Mode::Implied, 0x2B),
+    ("REP", Mode::Immediate, 0xC2),
+    ("RTL", Mode::Implied, 0x6B),
+    ("SBC", Mode::StackRelative, 0xE3),
+    ("SBC", Mode::ZeroPageIndirectLong, 0xE7),
+    ("SBC", Mode::AbsoluteLong, 0xEF),
+    ("SBC", Mode::StackRelativeIndirectY, 0xF3),
+    ("SBC", Mode::ZeroPageIndirectLongY, 0xF7),
+    ("SBC", Mode::AbsoluteLongX, 0xFF),
+    ("SEP", Mode::Immediate, 0xE2),
+    ("STA", Mode::StackRelative, 0x83),
+    ("STA", Mode::ZeroPageIndirectLong, 0x87),
+    ("STA", Mode::AbsoluteLong, 0x8F),
+    ("STA", Mode::StackRelativeIndirectY, 0x93),
+    ("STA", Mode::ZeroPageIndirectLongY, 0x97),
+    ("STA", Mode::AbsoluteLongX, 0x9F),
+    ("STP", Mode::Implied, 0xDB),
+    ("TCD", Mode::Implied, 0x5B),
+    ("TCS", Mode::Implied, 0x1B),
+    ("TDC", Mode::Implied, 0x7B),
+    ("TSC", Mode::Implied, 0x3B),
+    ("TXY", Mode::Implied, 0x9B),
+    ("TYX", Mode::Implied, 0xBB),
+    ("WAI", Mode::Implied, 0xCB),
+    ("WDM", Mode::Immediate, 0x42),
+    ("XBA", Mode::Implied, 0xEB),
+    ("XCE", Mode::Implied, 0xFB),
+];
+
+impl Backend for Mos6502Assembler {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool {
+        use Mode::*;
+
+        if self.width(asm, i)
+            || self.folding(asm, i)
+            || self.basic_stub(asm, i)
+            || self.region(asm, i)
+            || (self.variant == Variant::Ricoh && self.ines.directive(asm, i, 0xEA))
+            || (self.variant == Variant::M6507 && self.banks.directive(asm, i, 0xEA))
+            || (self.variant != Variant::W65816 && simulator::directive(asm, i))
+        {
+            return true;
+        }
+        let m = i.mnemonic.as_str();
+        let opcodes: Vec<(Mode, u8)> = self
+            .tables()
+            .iter()
+            .flat_map(|t| t.iter())
+            .filter(|(n, _, _)| *n == m)
+            .map(|(_, mode, opcode)| (*mode, *opcode))
+            .collect();
+        if opcodes.is_empty() {
+            if Self::all_tables()
+                .iter()
+                .any(|t| t.iter().any(|(n, _, _)| *n == m))
+            {
+                asm.error(&format!("{} not available on this processor", m));
+            }
+            return false;
+        }
+        let opcode = |mode: Mode| opcodes.iter().find(|(o, _)| *o == mode).map(|(_, o)| *o);
+        let address = asm.address;
+
+        // The operand syntax, and the addressing modes it can stand for,
+        // from the shortest to the longest
+        let (e, modes): (_, &[Mode]) = match i.parameters.as_slice() {
+            [] => (None, &[Implied, Accumulator]),
+            [a] if self.is(a, "A") => (None, &[Accumulator]),
+            [Expression::Immediate(e)] => (Some(e.as_ref()), &[Immediate]),
+            [Expression::Parenthesized(l)] => match l.as_slice() {
+                [e] => (Some(e), &[ZeroPageIndirect, Indirect]),
+                [e, x] if self.is(x, "X") => (Some(e), &[IndirectX, AbsoluteIndirectX]),
+                _ => asm.error(&format!("invalid indirect parameter for {}", m)),
+            },
+            [Expression::Parenthesized(l), y] if self.is(y, "Y") => match l.as_slice() {
+                [e] => (Some(e), &[IndirectY]),
+                [e, s] if self.is(s, "S") => (Some(e), &[StackRelativeIndirectY]),
+                _ => asm.error(&format!("invalid indirect parameter for {}", m)),
+            },
+            [Expression::Memory(e)] => (
+                Some(e.as_ref()),
+                &[ZeroPageIndirectLong, AbsoluteIndirectLong],
+            ),
+            [Expression::Memory(e), y] if self.is(y, "Y") => {
+                (Some(e.as_ref()), &[ZeroPageIndirectLongY])
+            }
+            [e, x] if self.is(x, "X") => (Some(e), &[ZeroPageX, AbsoluteX, AbsoluteLongX]),
+            [e, y] if self.is(y, "Y") => (Some(e), &[ZeroPageY, AbsoluteY]),
+            [e, s] if self.is(s, "S") => (Some(e), &[StackRelative]),
+            [e] if opcode(Relative).is_some() => (Some(e), &[Relative]),
+            [e] if opcode(RelativeLong).is_some() => (Some(e), &[RelativeLong]),
+            [e] => (Some(e), &[ZeroPage, Absolute, AbsoluteLong]),
+            [z, e] if opcode(ZeroPageRelative).is_some() => {
+                self.zero_page_relative(asm, i, opcode(ZeroPageRelative).unwrap(), z, e);
+                self.timing(asm, m, ZeroPageRelative, address, asm.evaluate(e));
+                return true;
+            }
+            [s, d] if opcode(BlockMove).is_some() => {
+                self.block_move(asm, i, opcode(BlockMove).unwrap(), s, d);
+                return true;
+            }
+            _ => asm.error(&format!("invalid parameters for {}", m)),
+        };
+
+        // The shortest mode that fits the address, where unknown
+        // addresses are assumed to be outside of zero page
+        let available: Vec<Mode> = modes
+            .iter()
+            .copied()
+            .filter(|m| opcode(*m).is_some())
+            .collect();
+        let v = e
+            .and_then(|e| asm.evaluate(e))
+            .map(|v| self.mirror(v))
+            .unwrap_or(256);
+        let mode = match available
+            .iter()
+            .find(|m| (0..=self.range(**m)).contains(&v))
+        {
+            Some(mode) => *mode,
+            None => match available.last() {
+                Some(mode) => *mode,
+                None => asm.error(&format!("invalid addressing mode for {}", m)),
+            },
+        };
+        let opcode = opcode(mode).unwrap();
+        if self.variant == Variant::Ricoh {
+            match m {
+                "SED" => {
+                    asm.warning("SED has no effect, the 2A03 has no decimal mode");
+                    self.decimal = true;
+                }
+                "CLD" => self.decimal = false,
+                "ADC" | "SBC" if self.decimal => {
+                    asm.warning(&format!("{} after SED is binary on the 2A03", m))
+                }
+                _ => (),
+            }
+        }
+        if self.variant == Variant::Undocumented && unstable(opcode) {
+            asm.warning(&format!("{} opcode 0x{:02X} is unstable", m, opcode));
+        }
+        match (mode, e) {
+            (Implied | Accumulator, _) => asm.emit(m, &[opcode], None),
+            (Immediate, Some(e)) => {
+                // Negative values are written in two's complement, like
+                // byte does
+                let p = asm.value(e);
+                if self.immediate16(m) {
+                    asm.check(
+                        (-32768..=65535).contains(&p),
+                        &format!("invalid parameter value for {}", m),
+                    );
+                    asm.emit(
+                        m,
+                        &[opcode, (p & 255) as u8, ((p >> 8) & 255) as u8],
+                        Some(p),
+                    );
+                } else {
+                    asm.check(
+                        (-128..=255).contains(&p),
+                        &format!("invalid parameter value for {}", m),
+                    );
+                    asm.emit(m, &[opcode, (p & 255) as u8], Some(p));
+                }
+                // REP and SEP set and clear the M and X flags
+                match m {
+                    "REP" | "SEP" => {
+                        if p & 0x20 != 0 {
+                            self.accumulator16 = m == "REP";
+                        }
+                        if p & 0x10 != 0 {
+                            self.index16 = m == "REP";
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            (Relative, Some(e)) => {
+                let offset = asm.value(e) - i64::from(asm.address) - 2;
+                asm.check(
+                    (-128..=127).contains(&offset),
+                    &format!("branch out of range for {}", m),
+                );
+                asm.emit(m, &[opcode, (offset & 255) as u8], Some(offset & 255));
+            }
+            (RelativeLong, Some(e)) => {
+                let offset = asm.value(e) - i64::from(asm.address) - 3;
+                asm.check(
+                    (-32768..=32767).contains(&offset),
+                    &format!("branch out of range for {}", m),
+                );
+                asm.emit(
+                    m,
+                    &[opcode, (offset & 255) as u8, ((offset >> 8) & 255) as u8],
+                    Some(offset & 65535),
+                );
+            }
+            (_, Some(e)) => {
+                let p = asm.value(e);
+                // Code in the cartridge can use any mirror of it, like
+                // $F000, but other addresses above $1FFF are suspicious
+                let cartridge = p & 0x1000 != 0 && address & 0x1000 != 0;
+                if self.variant == Variant::M6507 && !self.fold && p > 0x1FFF && !cartridge {
+                    asm.warning(&format!(
+                        "address {:#06X} outside the 6507 8K window, use fold to mirror it",
+                        p
+                    ));
+                }
+                let p = self.bank(mode, address, self.mirror(p));
+                let range = self.range(mode);
+                if range == 0xFFFF && p > 0xFFFF && ["JMP", "JSR"].contains(&m) {
+                    let long = if m == "JMP" { "JML" } else { "JSL" };
+                    asm.error(&format!("target of {} in another bank, use {}", m, long));
+                }
+                asm.check(
+                    (0..=range).contains(&p),
+                    &format!("invalid address for {}", m),
+                );
+                let bytes = [
+                    opcode,
+                    (p & 255) as u8,
+                    ((p >> 8) & 255) as u8,
+                    ((p >> 16) & 255) as u8,
+                ];
+                let size = match range {
+                    0xFF => 2,
+                    0xFFFF => 3,
+                    _ => 4,
+                };
+                asm.emit(m, &bytes[..size], Some(p));
+            }
+            (_, None) => asm.error(&format!("missing parameter for {}", m)),
+        }
+        let operand = e.and_then(|e| asm.evaluate(e)).map(|v| self.mirror(v));
+        self.timing(asm, m, mode, address, operand);
+        true
+    }
+
+    fn fill(&self) -> u16 {
+        0xEA
+    }
+
+    // The 65816 has 256 banks of 64K
+    fn max_address(&self) -> u32 {
+        if self.variant == Variant::W65816 {
+            0xFFFFFF
+        } else {
+            0xFFFF
+        }
+    }
+
+    fn start_pass(&mut self) {
+        self.accumulator16 = false;
+        self.index16 = false;
+        self.decimal = false;
+        self.fold = false;
+        self.ines = InesHeader::new();
+        self.banks = Bankswitch::new();
+        self.regions.clear();
+    }
+
+    fn end_pass(&mut self, asm: &mut Assembly) {
+        if let Some((line, _, _)) = self.regions.first() {
+            asm.error(&format!("cycles_begin at line {} without cycles_end", line));
+        }
+        self.banks.end_pass(asm, 0xEA);
+        self.ines.end_pass(asm, 0xEA);
+    }
+
+    fn symbols(&self) -> &'static [(&'static str, i64)] {
+        match self.variant {
+            Variant::Ricoh => &NES_SYMBOLS,
+            Variant::M6507 => &VCS_SYMBOLS,
+            _ => &[],
+        }
+    }
+
+    fn header(&self) -> Vec<u8> {
+        self.ines.bytes()
+    }
+}
+
+impl Mos6502Assembler {
+    // Opcode tables available on this variant
+    fn tables(&self) -> Vec<&'static [(&'static str, Mode, u8)]> {
+        match self.variant {
+            Variant::Nmos | Variant::Ricoh | Variant::M6507 => vec![&NMOS_OPCODES],
+            Variant::Undocumented => vec![&NMOS_OPCODES, &UNDOCUMENTED_OPCODES, &UNSTABLE_OPCODES],
+            Variant::Cmos => vec![&NMOS_OPCODES, &CMOS_OPCODES],
+            Variant::Rockwell => vec![&NMOS_OPCODES, &CMOS_OPCODES, &ROCKWELL_OPCODES],
+            Variant::W65816 => vec![&NMOS_OPCODES, &CMOS_OPCODES, &W65816_OPCODES],
+        }
+    }
+
+    // Mnemonic and addressing mode of an opcode on this variant
+    pub fn opcode(&self, opcode: u8) -> Option<(&'static str, Mode)> {
+        self.tables()
+            .iter()
+            .flat_map(|t| t.iter())
+            .find(|(_, _, o)| *o == opcode)
+            .map(|(m, mode, _)| (*m, *mode))
+    }
+
+    fn all_tables() -> Vec<&'static [(&'static str, Mode, u8)]> {
+        vec![
+            &NMOS_OPCODES,
+            &UNDOCUMENTED_OPCODES,
+            &UNSTABLE_OPCODES,
+            &CMOS_OPCODES,
+            &ROCKWELL_OPCODES,
+            &W65816_OPCODES,
+        ]
+    }
+
+    // Whether an operand is a given register name
+    fn is(&self, e: &Expression, name: &str) -> bool {
+        matches!(e, Expression::Identifier(s) if s == name)
+    }
+
+    // Highest address that a mode can encode
+    fn range(&self, mode: Mode) -> i64 {
+        use Mode::*;
+
+        match mode {
+            ZeroPage
+            | ZeroPageX
+            | ZeroPageY
+            | IndirectX
+            | IndirectY
+            | ZeroPageIndirect
+            | ZeroPageIndirectLong
+            | ZeroPageIndirectLongY
+            | StackRelative
+            | StackRelativeIndirectY => 0xFF,
+            AbsoluteLong | AbsoluteLongX => 0xFFFFFF,
+            _ => 0xFFFF,
+        }
+    }
+
+    // On the 65816, a 16-bit operand addresses the current bank, so an
+    // address in the bank of the code keeps only its low 16 bits. Data
+    // is assumed to be in that bank too, as after PHK and PLB
+    fn bank(&self, mode: Mode, address: u32, p: i64) -> i64 {
+        use Mode::*;
+
+        match mode {
+            Absolute | AbsoluteX | AbsoluteY | AbsoluteIndirectX
+                if self.variant == Variant::W65816 && p >> 16 == i64::from(address >> 16) =>
+            {
+                p & 0xFFFF
+            }
+            _ => p,
+        }
+    }
+
+    // Whether an immediate value is 16-bit, which depends on the M flag
+    // for the accumulator and on the X flag for the index registers
+    fn immediate16(&self, m: &str) -> bool {
+        match m {
+            "ADC" | "AND" | "BIT" | "CMP" | "EOR" | "LDA" | "ORA" | "SBC" => self.accumulator16,
+            "CPX" | "CPY" | "LDX" | "LDY" => self.index16,
+            _ => false,
+        }
+    }
+
+    // The basic_stub directive, a tokenized line n SYS address of
+    // Commodore BASIC, which starts the machine code when RUN
+    fn basic_stub(&self, asm: &mut Assembly, i: &Instruction) -> bool {
+        if i.mnemonic != "basic_stub" {
+            return false;
+        }
+        let (target, line) = match i.parameters.as_slice() {
+            [t] => (asm.value(t), 10),
+            [t, n] => (asm.value(t), asm.value(n)),
+            _ => asm.error("invalid parameters for basic_stub"),
+        };
+        asm.check(
+            (0..=65535).contains(&target),
+            "invalid address for basic_stub",
+        );
+        asm.check((0..=63999).contains(&line), "invalid line for basic_stub");
+        let digits = target.to_string();
+        // Link to the next line, line number, SYS token, address,
+        // end of line, then an empty link that ends the program
+        let next = i64::from(asm.address) + 6 + digits.len() as i64;
+        let mut bytes = vec![
+            (next & 255) as u8,
+            ((next >> 8) & 255) as u8,
+            (line & 255) as u8,
+            (line >> 8) as u8,
+            0x9E,
+        ];
+        bytes.extend_from_slice(digits.as_bytes());
+        bytes.extend_from_slice(&[0, 0, 0]);
+        for b in bytes {
+            asm.emit_byte(b);
+        }
+        true
+    }
+
+    // The a8, a16, i8 and i16 directives, on the 65816
+    fn width(&mut self, asm: &Assembly, i: &Instruction) -> bool {
+        if self.variant != Variant::W65816 {
+            return false;
+        }
+        match i.mnemonic.as_str() {
+            "a8" => self.accumulator16 = false,
+            "a16" => self.accumulator16 = true,
+            "i8" => self.index16 = false,
+            "i16" => self.index16 = true,
+            _ => return false,
+        }
+        asm.no_parameter(i);
+        true
+    }
+
+    // The fold directive, on the 6507
+    fn folding(&mut self, asm: &Assembly, i: &Instruction) -> bool {
+        if self.variant != Variant::M6507 || i.mnemonic != "fold" {
+            return false;
+        }
+        asm.no_parameter(i);
+        self.fold = true;
+        true
+    }
+
+    // The cycles_begin and cycles_end directives
+    fn region(&mut self, asm: &Assembly, i: &Instruction) -> bool {
+        match i.mnemonic.as_str() {
+            "cycles_begin" | "cycles_end" if self.variant == Variant::W65816 => {
+                asm.error("no cycle counts on the 65816")
+            }
+            "cycles_begin" => {
+                asm.no_parameter(i);
+                self.regions.push((asm.line, 0, 0));
+            }
+            "cycles_end" => {
+                let (line, fewest, most) = match self.regions.pop() {
+                    Some(r) => r,
+                    None => asm.error("cycles_end without cycles_begin"),
+                };
+                if asm.final_pass {
+                    if fewest == most {
+                        println!("# {} cycles from line {}", most, line);
+                    } else {
+                        println!("# {} to {} cycles from line {}", fewest, most, line);
+                    }
+                }
+                match i.parameters.as_slice() {
+                    [] => (),
+                    [n] => {
+                        let n = asm.value(n);
+                        asm.check(
+                            i64::from(most) <= n,
+                            &format!("{} cycles from line {}, more than {}", most, line, n),
+                        );
+                    }
+                    _ => asm.error("invalid parameters for cycles_end"),
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // List the cycles of an instruction, and add them to the open
+    // regions
+    fn timing(&mut self, asm: &Assembly, m: &str, mode: Mode, address: u32, operand: Option<i64>) {
+        use Mode::*;
+
+        let base = match cycles(m, mode) {
+            Some(c) if self.variant != Variant::W65816 => c,
+            _ => return,
+        };
+        let (most, note) = match mode {
+            // One more cycle when taken, two when the target is in
+            // another page than the next instruction
+            Relative | ZeroPageRelative => {
+                let next = address + if mode == Relative { 2 } else { 3 };
+                let across = operand.is_some_and(|t| (t as u32) & 0xFF00 != next & 0xFF00);
+                let taken = base + if across { 2 } else { 1 };
+                let note = if across {
+                    "if taken across a page"
+                } else {
+                    "if taken"
+                };
+                (taken, note)
+            }
+            // An index can't cross a page from the start of one
+            AbsoluteX | AbsoluteY if operand.is_some_and(|a| a & 0xFF == 0) => (base, ""),
+            _ if page_penalty(m, mode) => (base + 1, "if the index crosses a page"),
+            _ => (base, ""),
+        };
+        let fewest = if m == "BRA" { most } else { base };
+        if asm.final_pass {
+            if fewest == most {
+                println!("# {} takes {} cycles", m, most);
+            } else {
+                println!("# {} takes {} cycles, {} {}", m, fewest, most, note);
+            }
+        }
+        for r in &mut self.regions {
+            r.1 += fewest;
+            r.2 += most;
+        }
+    }
+
+    // Fold an address into the 8K window of the 6507, where the
+    // upper address lines aren't connected
+    fn mirror(&self, address: i64) -> i64 {
+        if self.fold && (0..=0xFFFF).contains(&address) {
+            address & 0x1FFF
+        } else {
+            address
+        }
+    }
+
+    // MVN and MVP, source bank first, which are encoded the other way
+    fn block_move(
+        &self,
+        asm: &mut Assembly,
+        i: &Instruction,
+        opcode: u8,
+        s: &Expression,
+        d: &Expression,
+    ) {
+        let s = asm.value(s);
+        let d = asm.value(d);
+        asm.check(
+            (0..=255).contains(&s) && (0..=255).contains(&d),
+            &format!("invalid bank for {}", i.mnemonic),
+        );
+        asm.emit(
+            &i.mnemonic,
+            &[opcode, (d & 255) as u8, (s & 255) as u8],
+            None,
+        );
+    }
+
+    // BBR and BBS, which test a bit in zero page and branch
+    fn zero_page_relative(
+        &self,
+        asm: &mut Assembly,
+        i: &Instruction,
+        opcode: u8,
+        z: &Expression,
+        e: &Expression,
+    ) {
+        let z = asm.value(z);
+        asm.check(
+            (0..=255).contains(&z),
+            &format!("invalid zero page address for {}", i.mnemonic),
+        );
+        let offset = asm.value(e) - i64::from(asm.address) - 3;
+        asm.check(
+            (-128..=127).contains(&offset),
+            &format!("branch out of range for {}", i.mnemonic),
+        );
+        asm.emit(
+            &i.mnemonic,
+            &[opcode, (z & 255) as u8, (offset & 255) as u8],
+            Some(offset & 255),
+        );
+    }
+}
+
+// Cycles taken by an instruction on the NMOS 6502, before the extra
+// cycles for crossing a page and for taking a branch, or None for the
+// 65816 modes
+pub fn cycles(m: &str, mode: Mode) -> Option<u32> {
+    use Mode::*;
+
+    // Read-modify-write instructions read and write their operand
+    let rmw = matches!(
+        m,
+        "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" | "TSB" | "TRB"
+    ) || matches!(m, "SLO" | "RLA" | "SRE" | "RRA" | "DCP" | "ISC")
+        || m.starts_with("RMB")
+        || m.starts_with("SMB");
+    let store = matches!(m, "STA" | "STX" | "STY" | "STZ" | "SAX" | "AHX" | "TAS")
+        || matches!(m, "SHX" | "SHY");
+    let cycles = match (m, mode) {
+        ("BRK", _) => 7,
+        ("RTI" | "RTS" | "JSR", _) => 6,
+        ("JMP", Absolute) => 3,
+        ("JMP", Indirect) => 5,
+        ("JMP", AbsoluteIndirectX) => 6,
+        ("PHA" | "PHP" | "PHX" | "PHY", _) => 3,
+        ("PLA" | "PLP" | "PLX" | "PLY", _) => 4,
+        (_, Implied | Accumulator | Immediate | Relative) => 2,
+        (_, ZeroPage) if rmw => 5,
+        (_, ZeroPage) => 3,
+        (_, ZeroPageX | ZeroPageY | Absolute) if rmw => 6,
+        (_, ZeroPageX | ZeroPageY | Absolute) => 4,
+        (_, AbsoluteX | AbsoluteY) if rmw => 7,
+        (_, AbsoluteX | AbsoluteY) if store => 5,
+        (_, AbsoluteX | AbsoluteY) => 4,
+        (_, IndirectX) if rmw => 8,
+        (_, IndirectX) => 6,
+        (_, IndirectY) if rmw => 8,
+        (_, IndirectY) if store => 6,
+        (_, IndirectY) => 5,
+        (_, ZeroPageIndirect | ZeroPageRelative) => 5,
+        _ => return None,
+    };
+    Some(cycles)
+}
+
+// Whether an instruction takes one more cycle when its indexed address
+// crosses a page, which only reads do
+pub fn page_penalty(m: &str, mode: Mode) -> bool {
+    matches!(mode, Mode::AbsoluteX | Mode::AbsoluteY | Mode::IndirectY)
+        && cycles(m, mode).is_some_and(|c| c == if mode == Mode::IndirectY { 5 } else { 4 })
+}
+
+impl Variant {
+    // The variant for the name given to the processor directive
+    pub fn named(name: &str) -> Option<Variant> {
+        match name {
+            "6502" => Some(Variant::Nmos),
+            "2a03" => Some(Variant::Ricoh),
+            "6507" => Some(Variant::M6507),
+            "6502x" => Some(Variant::Undocumented),
+            "65c02" => Some(Variant::Cmos),
+            "r65c02" => Some(Variant::Rockwell),
+            "65816" => Some(Variant::W65816),
+            _ => None,
+        }
+    }
+}
+
+// A decoded instruction, or a byte that isn't one
+enum Disassembled {
+    Instruction(&'static str, Mode, Vec<u8>),
+    Byte(u8),
+}
+
+// Disassembler, from the same opcode tables
+//
+// Anything that wouldn't assemble back to the same bytes is written as
+// byte directives: unknown opcodes, instructions cut by the end of the
+// image, and long addressing modes for addresses that fit in a shorter
+// one, which the assembler would pick instead.
+impl Mos6502Assembler {
+    pub fn disassemble(&mut self, processor: &str, bytes: &[u8], origin: u32) -> String {
+        use Mode::*;
+
+        let mut lines: Vec<(u32, Disassembled)> = Vec::new();
+        let mut position = 0;
+        while position < bytes.len() {
+            let address = origin + position as u32;
+            let line = match self.decode(&bytes[position..], address) {
+                Some((m, mode, b)) => {
+                    // REP and SEP change the size of immediate values
+                    if let ("REP" | "SEP", [_, p]) = (m, b.as_slice()) {
+                        if p & 0x20 != 0 {
+                            self.accumulator16 = m == "REP";
+                        }
+                        if p & 0x10 != 0 {
+                            self.index16 = m == "REP";
+                        }
+                    }
+                    position += b.len();
+                    Disassembled::Instruction(m, mode, b)
+                }
+                None => {
+                    position += 1;
+                    Disassembled::Byte(bytes[position - 1])
+                }
+            };
+            lines.push((address, line));
+        }
+
+        // Branch and jump targets get labels, when they're at the start
+        // of a line
+        let targets: Vec<u32> = lines
+            .iter()
+            .filter_map(|(address, d)| match d {
+                Disassembled::Instruction(m, mode, b) => match mode {
+                    Relative | RelativeLong | ZeroPageRelative => {
+                        Some(self.target(*address, *mode, b))
+                    }
+                    Absolute | AbsoluteLong if ["JMP", "JSR", "JML", "JSL"].contains(m) => {
+                        Some(Self::operand(b))
+                    }
+                    _ => None,
+                },
+                Disassembled::Byte(_) => None,
+            })
+            .filter(|t| lines.iter().any(|(a, _)| a == t))
+            .collect();
+        let label = |address: u32| -> Option<String> {
+            if targets.contains(&address) {
+                Some(format!("L{:04X}", address))
+            } else {
+                None
+            }
+        };
+        let name = |address: u32, digits: usize| -> String {
+            label(address).unwrap_or(format!("${:0digits$X}", address))
+        };
+
+        let mut out = format!(" processor {}\n org ${:04X}\n", processor, origin);
+        let mut pending: Vec<String> = Vec::new();
+        for (address, d) in &lines {
+            let l = label(*address);
+            if !pending.is_empty()
+                && (l.is_some() || pending.len() == 8 || !matches!(d, Disassembled::Byte(_)))
+            {
+                out.push_str(&format!("\tbyte\t{}\n", pending.join(",")));
+                pending.clear();
+            }
+            if let Some(l) = l {
+                out.push_str(&format!("{}:\n", l));
+            }
+            let (m, mode, b) = match d {
+                Disassembled::Byte(b) => {
+                    pending.push(format!("${:02X}", b));
+                    continue;
+                }
+                Disassembled::Instruction(m, mode, b) => (m, *mode, b),
+            };
+            let v = Self::operand(b);
+            let digits = match self.range(mode) {
+                0xFF => 2,
+                0xFFFF => 4,
+                _ => 6,
+            };
+            let operand = match mode {
+                Implied => String::new(),
+                Accumulator => String::from("A"),
+                Immediate => format!("#${:0w$X}", v, w = 2 * (b.len() - 1)),
+                ZeroPage | Absolute | AbsoluteLong => name(v, digits),
+                ZeroPageX | AbsoluteX | AbsoluteLongX => format!("{},X", name(v, digits)),
+                ZeroPageY | AbsoluteY => format!("{},Y", name(v, digits)),
+                ZeroPageIndirect | Indirect => format!("({})", name(v, digits)),
+                IndirectX | AbsoluteIndirectX => format!("({},X)", name(v, digits)),
+                IndirectY => format!("({}),Y", name(v, digits)),
+                ZeroPageIndirectLong | AbsoluteIndirectLong => format!("[{}]", name(v, digits)),
+                ZeroPageIndirectLongY => format!("[{}],Y", name(v, digits)),
+                StackRelative => format!("${:02X},S", v),
+                StackRelativeIndirectY => format!("(${:02X},S),Y", v),
+                Relative | RelativeLong => name(self.target(*address, mode, b), 4),
+                ZeroPageRelative => {
+                    format!("${:02X},{}", b[1], name(self.target(*address, mode, b), 4))
+                }
+                BlockMove => format!("${:02X},${:02X}", b[2], b[1]),
+            };
+            if operand.is_empty() {
+                out.push_str(&format!("\t{}\n", m));
+            } else {
+                out.push_str(&format!("\t{}\t{}\n", m, operand));
+            }
+        }
+        if !pending.is_empty() {
+            out.push_str(&format!("\tbyte\t{}\n", pending.join(",")));
+        }
+        out
+    }
+
+    // Decode the instruction at the start of some bytes, if it assembles
+    // back to the same bytes
+    fn decode(&self, bytes: &[u8], address: u32) -> Option<(&'static str, Mode, Vec<u8>)> {
+        use Mode::*;
+
+        let (m, mode) = self.opcode(bytes[0])?;
+        let size = match mode {
+            Implied | Accumulator => 1,
+            Immediate if self.immediate16(m) => 3,
+            Immediate | Relative => 2,
+            ZeroPageRelative | RelativeLong | BlockMove => 3,
+            _ => match self.range(mode) {
+                0xFF => 2,
+                0xFFFF => 3,
+                _ => 4,
+            },
+        };
+        let b = bytes.get(..size)?.to_vec();
+        // The assembler picks the first of these modes where the address
+        // fits, so a longer one can't be written for a short address
+        let shorter: &[Mode] = match mode {
+            Absolute => &[ZeroPage],
+            AbsoluteLong => &[ZeroPage, Absolute],
+            AbsoluteX => &[ZeroPageX],
+            AbsoluteLongX => &[ZeroPageX, AbsoluteX],
+            AbsoluteY => &[ZeroPageY],
+            Indirect => &[ZeroPageIndirect],
+            AbsoluteIndirectX => &[IndirectX],
+            AbsoluteIndirectLong => &[ZeroPageIndirectLong],
+            _ => &[],
+        };
+        let v = i64::from(Self::operand(&b));
+        let available = |s: Mode| {
+            self.tables()
+                .iter()
+                .any(|t| t.iter().any(|(n, o, _)| *n == m && *o == s))
+        };
+        if shorter.iter().any(|s| available(*s) && v <= self.range(*s)) {
+            return None;
+        }
+        // The assembler can't write a branch that wraps around its bank
+        if matches!(mode, Relative | RelativeLong | ZeroPageRelative)
+            && i64::from(self.target(address, mode, &b)) != Self::branch(address, mode, &b)
+        {
+            return None;
+        }
+        Some((m, mode, b))
+    }
+
+    // The operand of an instruction, low byte first
+    fn operand(bytes: &[u8]) -> u32 {
+        bytes[1..]
+            .iter()
+            .rev()
+            .fold(0, |v, b| (v << 8) | u32::from(*b))
+    }
+
+    // Destination of a branch, before it wraps around
+    fn branch(address: u32, mode: Mode, bytes: &[u8]) -> i64 {
+        let address = i64::from(address);
+        match mode {
+            Mode::Relative => address + 2 + i64::from(bytes[1] as i8),
+            Mode::ZeroPageRelative => address + 3 + i64::from(bytes[2] as i8),
+            _ => address + 3 + i64::from(i16::from_le_bytes([bytes[1], bytes[2]])),
+        }
+    }
+
+    // Destination of a branch, which stays in the bank of the branch
+    // like the program counter does
+    fn target(&self, address: u32, mode: Mode, bytes: &[u8]) -> u32 {
+        (address & !0xFFFF) | (Self::branch(address, mode, bytes) as u32 & 0xFFFF)
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Output files, in the formats that programmers and emulators read
+//
+// The format comes from the extension of the file: .hex for Intel HEX,
+// .s19, .s28, .s37, .srec or .mot for Motorola S-records, .prg for
+// Commodore programs, .b for Apple DOS 3.3 binaries, .as for
+// AppleSingle, .xex for Atari 8-bit executables, and anything else for
+// a raw binary, header included.
+
+use std::collections::HashMap;
+
+use crate::simulator::Test;
+
+// The assembled code, as laid out in the output
+pub struct Image {
+    // Address of the first byte, counted in bytes even on word-addressed
+    // processors
+    pub origin: u32,
+    // File header, outside of the address space
+    pub header: Vec<u8>,
+    pub bytes: Vec<u8>,
+    // Blocks of the image: address, offset in the image, and whether
+    // it's padding from org
+    pub sections: Vec<(u32, usize, bool)>,
+    // Init addresses, with the offset in the image where they appear
+    pub inits: Vec<(usize, u32)>,
+    // Start address from the entry directive, with its segment if any
+    pub entry: Option<(Option<u32>, u32)>,
+    // Whether addresses are made of a segment and an offset
+    pub segmented: bool,
+    // ProDOS file type and aux type, from the filetype and auxtype
+    // directives
+    pub file_type: Option<u16>,
+    pub aux_type: Option<u16>,
+    // Processor and final values of the symbols, for the simulator
+    pub processor: String,
+    pub symbols: HashMap<String, i64>,
+    // Test blocks, for asm7x test
+    pub tests: Vec<Test>,
+}
+
+pub fn write(image: &Image, path: &str, record_length: usize) {
+    let data = match path.rsplit_once('.').map(|(_, e)| e.to_lowercase()) {
+        Some(e) if e == "hex" || e == "ihx" => intel_hex(image, record_length).into_bytes(),
+        Some(e) if ["s19", "s28", "s37", "srec", "mot"].contains(&e.as_str()) => {
+            srecord(image, record_length).into_bytes()
+        }
+        // Load address, low byte first
+        Some(e) if e == "prg" => {
+            [&(image.origin as u16).to_le_bytes(), image.bytes.as_slice()].concat()
+        }
+        // Load address and length, low bytes first
+        Some(e) if e == "b" => {
+            if image.bytes.len() > 0xFFFF {
+                println!("image too large for DOS 3.3");
+                panic!("unimplemented error handling")
+            }
+            [
+                &(image.origin as u16).to_le_bytes(),
+                &(image.bytes.len() as u16).to_le_bytes(),
+                image.bytes.as_slice(),
+            ]
+            .concat()
+        }
+        Some(e) if e == "as" => apple_single(image, path),
+        Some(e) if e == "xex" => xex(image),
+        _ => [image.header.as_slice(), image.bytes.as_slice()].concat(),
+    };
+    if let Err(e) = std::fs::write(path, data) {
+        println!("cannot write {}: {}", path, e);
+        panic!("unimplemented error handling")
+    }
+}
+
+// One Intel HEX record, with its byte count and checksum
+fn record(out: &mut String, kind: u8, address: u16, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+    out.push(':');
+    for b in bytes {
+        out.push_str(&format!("{:02X}", b));
+    }
+    out.push('\n');
+}
+
+// Intel HEX, with extended segment address records on segmented
+// processors and extended linear address records otherwise, for the
+// parts of the image above 64K
+fn intel_hex(image: &Image, length: usize) -> String {
+    let mut out = String::new();
+    let mut upper = 0;
+    let mut position = 0;
+    while position < image.bytes.len() {
+        let address = image.origin + position as u32;
+        if address >> 16 != upper {
+            upper = address >> 16;
+            if image.segmented {
+                if upper > 0x0F {
+                    println!("image above 1MB");
+                    panic!("unimplemented error handling")
+                }
+                record(&mut out, 0x02, 0, &((upper << 12) as u16).to_be_bytes());
+            } else {
+                record(&mut out, 0x04, 0, &(upper as u16).to_be_bytes());
+            }
+        }
+        // Records don't cross 64K boundaries
+        let size = length
+            .min(image.bytes.len() - position)
+            .min(0x10000 - (address & 0xFFFF) as usize);
+        record(
+            &mut out,
+            0x00,
+            address as u16,
+            &image.bytes[position..position + size],
+        );
+        position += size;
+    }
+    match image.entry {
+        Some((Some(segment), offset)) => {
+            let data = [
+                (segment as u16).to_be_bytes(),
+                (offset as u16).to_be_bytes(),
+            ]
+            .concat();
+            record(&mut out, 0x03, 0, &data);
+        }
+        Some((None, offset)) if image.segmented => {
+            let data = [0u16.to_be_bytes(), (offset as u16).to_be_bytes()].concat();
+            record(&mut out, 0x03, 0, &data);
+        }
+        Some((None, address)) => record(&mut out, 0x05, 0, &address.to_be_bytes()),
+        None => (),
+    }
+    record(&mut out, 0x01, 0, &[]);
+    out
+}
+
+// Start address, as a linear address
+fn entry(image: &Image) -> u32 {
+    match image.entry {
+        Some((Some(segment), offset)) => (segment << 4) + offset,
+        Some((None, address)) => address,
+        None => 0,
+    }
+}
+
+// One S-record, where the count covers the address, the data and the
+// checksum
+fn srecord_line(out: &mut String, kind: u8, width: usize, address: u32, data: &[u8]) {
+    let mut bytes = vec![(width + data.len() + 1) as u8];
+    bytes.extend_from_slice(&address.to_be_bytes()[4 - width..]);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+    bytes.push(!sum);
+    out.push_str(&format!("S{}", kind));
+    for b in bytes {
+        out.push_str(&format!("{:02X}", b));
+    }
+    out.push('\n');
+}
+
+// Motorola S-records, S1 to S3 with 16-bit to 32-bit addresses
+// depending on where the image ends, and the matching S9 to S7
+// termination record with the start address
+fn srecord(image: &Image, length: usize) -> String {
+    let end = image.origin + image.bytes.len().max(1) as u32 - 1;
+    let (data, termination, width) = match end.max(entry(image)) {
+        0..=0xFFFF => (1, 9, 2),
+        0x10000..=0xFFFFFF => (2, 8, 3),
+        _ => (3, 7, 4),
+    };
+    let length = length.min(255 - width - 1);
+    let mut out = String::new();
+    for (n, chunk) in image.bytes.chunks(length).enumerate() {
+        let address = image.origin + (n * length) as u32;
+        srecord_line(&mut out, data, width, address, chunk);
+    }
+    srecord_line(&mut out, termination, width, entry(image), &[]);
+    out
+}
+
+// AppleSingle, with the name of the file, its ProDOS file information
+// and its data fork, where the file type defaults to BIN and the aux
+// type to the load address
+fn apple_single(image: &Image, path: &str) -> Vec<u8> {
+    let name = std::path::Path::new(path)
+        .file_stem()
+        .map_or(String::from("ASM7X"), |s| {
+            s.to_string_lossy().to_uppercase()
+        });
+    let name = &name.as_bytes()[..name.len().min(15)];
+    let mut info = vec![0x00, 0xC3];
+    info.extend_from_slice(&image.file_type.unwrap_or(0x06).to_be_bytes());
+    info.extend_from_slice(&u32::from(image.aux_type.unwrap_or(image.origin as u16)).to_be_bytes());
+    let entries: [(u32, &[u8]); 3] = [(3, name), (11, &info), (1, &image.bytes)];
+
+    let mut out = vec![0x00, 0x05, 0x16, 0x00, 0x00, 0x02, 0x00, 0x00];
+    out.resize(24, 0);
+    out.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    let mut offset = out.len() + 12 * entries.len();
+    for (id, data) in entries {
+        out.extend_from_slice(&id.to_be_bytes());
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        offset += data.len();
+    }
+    for (_, data) in entries {
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+// One segment of an Atari executable, start and end addresses first
+fn xex_segment(out: &mut Vec<u8>, address: u32, data: &[u8]) {
+    let end = address + data.len() as u32 - 1;
+    if end > 0xFFFF {
+        println!("segment above 64K at {}", address);
+        panic!("unimplemented error handling")
+    }
+    out.extend_from_slice(&(address as u16).to_le_bytes());
+    out.extend_from_slice(&(end as u16).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+// Atari 8-bit executable, with a segment for each block of the image
+// without its padding, INITAD segments where the init directives
+// appear, and a RUNAD segment for the start address
+fn xex(image: &Image) -> Vec<u8> {
+    let mut out = vec![0xFF, 0xFF];
+    let mut inits = image.inits.iter().peekable();
+    for (n, &(address, offset, fill)) in image.sections.iter().enumerate() {
+        if fill {
+            continue;
+        }
+        let end = image.sections.get(n + 1).map_or(image.bytes.len(), |s| s.1);
+        let mut start = offset;
+        loop {
+            let stop = match inits.peek() {
+                Some((o, _)) if *o <= end => (*o).max(start),
+                _ => end,
+            };
+            if stop > start {
+                let a = address + (start - offset) as u32;
+                xex_segment(&mut out, a, &image.bytes[start..stop]);
+            }
+            start = stop;
+            match inits.peek() {
+                Some((o, a)) if *o <= stop => {
+                    xex_segment(&mut out, 0x02E2, &(*a as u16).to_le_bytes());
+                    inits.next();
+                }
+                _ => break,
+            }
+        }
+    }
+    for (_, a) in inits {
+        xex_segment(&mut out, 0x02E2, &(*a as u16).to_le_bytes());
+    }
+    if let Some((_, run)) = image.entry {
+        xex_segment(&mut out, 0x02E0, &(run as u16).to_le_bytes());
+    }
+    out
+}
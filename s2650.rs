@@ -0,0 +1,350 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Signetics 2650 back end, as used in the Emerson Arcadia 2001 and
+// the Interton VC 4000
+//
+// Mnemonics carry a register or condition field after a comma, as in
+// LODA,R1 or BCTR,UN. The Z forms work between a register and R0.
+// A '*' before an address makes it indirect. Memory is made of 8K
+// pages: non-branch absolute addresses stay in the current page and
+// can be indexed (",R1", with a '+' or '-' suffix for auto-increment
+// or auto-decrement), while absolute branches reach the whole 32K.
+
+use crate::{Assembly, Backend, Expression, Instruction};
+
+pub struct S2650Assembler {}
+
+enum Form {
+    Implied,
+    // Register or condition field, no operand
+    Field,
+    // Register-zero forms, with the register in the field or as the operand
+    Zero,
+    // Register-zero forms where R0 would encode another instruction
+    ZeroNotR0,
+    Immediate,
+    // Program status instructions, with a mask and no field
+    StatusImmediate,
+    Relative,
+    Absolute,
+    BranchRelative,
+    BranchAbsolute,
+    // Branches on condition false, where UN is another instruction
+    BranchFalseRelative,
+    BranchFalseAbsolute,
+    // ZBRR and ZBSR, relative to address zero
+    ZeroPageBranch,
+    // BXA and BSXA, indexed by R3
+    IndexedBranch,
+}
+
+// Memory is made of 8K pages, absolute branches can reach 32K
+const PAGE_SIZE: i64 = 8192;
+const ADDRESS_SPACE: i64 = 32768;
+
+impl Backend for S2650Assembler {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool {
+        use Form::*;
+
+        let (base, field) = match i.mnemonic.split_once(',') {
+            Some((b, f)) => (b, Some(f)),
+            None => (i.mnemonic.as_str(), None),
+        };
+        let (opcode, form) = match base {
+            "LODZ" => (0x00, ZeroNotR0),
+            "LODI" => (0x04, Immediate),
+            "LODR" => (0x08, Relative),
+            "LODA" => (0x0C, Absolute),
+            "SPSU" => (0x12, Implied),
+            "SPSL" => (0x13, Implied),
+            "RETC" => (0x14, Field),
+            "BCTR" => (0x18, BranchRelative),
+            "BCTA" => (0x1C, BranchAbsolute),
+            "EORZ" => (0x20, Zero),
+            "EORI" => (0x24, Immediate),
+            "EORR" => (0x28, Relative),
+            "EORA" => (0x2C, Absolute),
+            "REDC" => (0x30, Field),
+            "RETE" => (0x34, Field),
+            "BSTR" => (0x38, BranchRelative),
+            "BSTA" => (0x3C, BranchAbsolute),
+            "HALT" => (0x40, Implied),
+            "ANDZ" => (0x40, ZeroNotR0),
+            "ANDI" => (0x44, Immediate),
+            "ANDR" => (0x48, Relative),
+            "ANDA" => (0x4C, Absolute),
+            "RRR" => (0x50, Field),
+            "REDE" => (0x54, Immediate),
+            "BRNR" => (0x58, BranchRelative),
+            "BRNA" => (0x5C, BranchAbsolute),
+            "IORZ" => (0x60, Zero),
+            "IORI" => (0x64, Immediate),
+            "IORR" => (0x68, Relative),
+            "IORA" => (0x6C, Absolute),
+            "REDD" => (0x70, Field),
+            "CPSU" => (0x74, StatusImmediate),
+            "CPSL" => (0x75, StatusImmediate),
+            "PPSU" => (0x76, StatusImmediate),
+            "PPSL" => (0x77, StatusImmediate),
+            "BSNR" => (0x78, BranchRelative),
+            "BSNA" => (0x7C, BranchAbsolute),
+            "ADDZ" => (0x80, Zero),
+            "ADDI" => (0x84, Immediate),
+            "ADDR" => (0x88, Relative),
+            "ADDA" => (0x8C, Absolute),
+            "LPSU" => (0x92, Implied),
+            "LPSL" => (0x93, Implied),
+            "DAR" => (0x94, Field),
+            "BCFR" => (0x98, BranchFalseRelative),
+            "ZBRR" => (0x9B, ZeroPageBranch),
+            "BCFA" => (0x9C, BranchFalseAbsolute),
+            "BXA" => (0x9F, IndexedBranch),
+            "SUBZ" => (0xA0, Zero),
+            "SUBI" => (0xA4, Immediate),
+            "SUBR" => (0xA8, Relative),
+            "SUBA" => (0xAC, Absolute),
+            "WRTC" => (0xB0, Field),
+            "TPSU" => (0xB4, StatusImmediate),
+            "TPSL" => (0xB5, StatusImmediate),
+            "BSFR" => (0xB8, BranchFalseRelative),
+            "ZBSR" => (0xBB, ZeroPageBranch),
+            "BSFA" => (0xBC, BranchFalseAbsolute),
+            "BSXA" => (0xBF, IndexedBranch),
+            "NOP" => (0xC0, Implied),
+            "STRZ" => (0xC0, ZeroNotR0),
+            "STRR" => (0xC8, Relative),
+            "STRA" => (0xCC, Absolute),
+            "RRL" => (0xD0, Field),
+            "WRTE" => (0xD4, Immediate),
+            "BIRR" => (0xD8, BranchRelative),
+            "BIRA" => (0xDC, BranchAbsolute),
+            "COMZ" => (0xE0, Zero),
+            "COMI" => (0xE4, Immediate),
+            "COMR" => (0xE8, Relative),
+            "COMA" => (0xEC, Absolute),
+            "WRTD" => (0xF0, Field),
+            "TMI" => (0xF4, Immediate),
+            "BDRR" => (0xF8, BranchRelative),
+            "BDRA" => (0xFC, BranchAbsolute),
+            _ => return false,
+        };
+
+        match form {
+            Implied | StatusImmediate | ZeroPageBranch | IndexedBranch => {
+                if field.is_some() {
+                    asm.error(&format!("unexpected field for {}", base));
+                }
+            }
+            Zero | ZeroNotR0 => (),
+            _ => {
+                if field.is_none() {
+                    asm.error(&format!("missing register or condition for {}", base));
+                }
+            }
+        }
+        let f = match field {
+            Some(f) => self.field(asm, base, f),
+            None => 0,
+        };
+
+        match form {
+            Implied => {
+                asm.no_parameter(i);
+                asm.emit(base, &[opcode], None);
+            }
+            Field => {
+                asm.no_parameter(i);
+                asm.emit(base, &[opcode | f], None);
+            }
+            Zero | ZeroNotR0 => {
+                let r = match (field, i.parameters.as_slice()) {
+                    (Some(_), []) => f,
+                    (None, [Expression::Identifier(r)]) => self.field(asm, base, r),
+                    _ => asm.error(&format!("{} needs exactly one register", base)),
+                };
+                if let ZeroNotR0 = form {
+                    if r == 0 {
+                        asm.error(&format!("{} can't use R0", base));
+                    }
+                }
+                asm.emit(base, &[opcode | r], None);
+            }
+            Immediate | StatusImmediate => {
+                let v = match asm.parameter(i) {
+                    Expression::Immediate(v) => asm.value(v),
+                    v => asm.value(v),
+                };
+                asm.check(
+                    (-128..=255).contains(&v),
+                    &format!("invalid parameter value for {}", base),
+                );
+                asm.emit(base, &[opcode | f, (v & 255) as u8], Some(v));
+            }
+            Relative | BranchRelative | BranchFalseRelative => {
+                if let BranchFalseRelative = form {
+                    if f == 3 {
+                        asm.error(&format!("{} can't use UN", base));
+                    }
+                }
+                let (indirect, destination) = self.address(asm, base, asm.parameter(i));
+                // Relative to the next instruction, wrapping within the page
+                asm.check(
+                    destination / PAGE_SIZE == i64::from(asm.address) / PAGE_SIZE,
+                    &format!(
+                        "{} to {} from {} leaves the current 8K page",
+                        base, destination, asm.address
+                    ),
+                );
+                let next = i64::from(asm.address) + 2;
+                let mut offset = (destination - next).rem_euclid(PAGE_SIZE);
+                if offset >= PAGE_SIZE / 2 {
+                    offset -= PAGE_SIZE;
+                }
+                asm.check(
+                    (-64..=63).contains(&offset),
+                    &format!("relative address out of range for {}", base),
+                );
+                asm.emit(
+                    base,
+                    &[opcode | f, indirect | (offset & 0x7F) as u8],
+                    Some(destination),
+                );
+            }
+            Absolute => {
+                let (a, index) = match i.parameters.as_slice() {
+                    [a] => (a, None),
+                    [a, x] => (a, Some(x)),
+                    _ => asm.error(&format!("wrong number of parameters for {}", base)),
+                };
+                let (indirect, destination) = self.address(asm, base, a);
+                asm.check(
+                    destination / PAGE_SIZE == i64::from(asm.address) / PAGE_SIZE,
+                    &format!(
+                        "{} to {} from {} leaves the current 8K page",
+                        base, destination, asm.address
+                    ),
+                );
+                // When indexing, the field holds the index register
+                // and the operation always applies to R0
+                let (r, control) = match index {
+                    None => (f, 0x00),
+                    Some(x) => {
+                        if f != 0 {
+                            asm.error(&format!("indexed {} must use R0", base));
+                        }
+                        match x {
+                            Expression::Identifier(x) => (self.field(asm, base, x), 0x60),
+                            Expression::PostIncrement(x) => (self.index(asm, base, x), 0x20),
+                            Expression::PostDecrement(x) => (self.index(asm, base, x), 0x40),
+                            _ => asm.error(&format!("invalid index for {}", base)),
+                        }
+                    }
+                };
+                if index.is_some() && r == 0 {
+                    asm.error(&format!("{} can't be indexed by R0", base));
+                }
+                asm.emit(
+                    base,
+                    &[
+                        opcode | r,
+                        indirect | control | ((destination >> 8) & 0x1F) as u8,
+                        (destination & 255) as u8,
+                    ],
+                    Some(destination),
+                );
+            }
+            BranchAbsolute | BranchFalseAbsolute | IndexedBranch => {
+                if let BranchFalseAbsolute = form {
+                    if f == 3 {
+                        asm.error(&format!("{} can't use UN", base));
+                    }
+                }
+                let a = match (&form, i.parameters.as_slice()) {
+                    (IndexedBranch, [a, Expression::Identifier(x)]) if x == "R3" => a,
+                    (IndexedBranch, [_, _]) => asm.error(&format!("{} is indexed by R3", base)),
+                    (_, [a]) => a,
+                    _ => asm.error(&format!("wrong number of parameters for {}", base)),
+                };
+                let (indirect, destination) = self.address(asm, base, a);
+                asm.emit(
+                    base,
+                    &[
+                        opcode | f,
+                        indirect | ((destination >> 8) & 0x7F) as u8,
+                        (destination & 255) as u8,
+                    ],
+                    Some(destination),
+                );
+            }
+            ZeroPageBranch => {
+                let (indirect, destination) = self.address(asm, base, asm.parameter(i));
+                // Relative to address zero, wrapping to the end of the page
+                let offset = if destination >= PAGE_SIZE / 2 {
+                    destination - PAGE_SIZE
+                } else {
+                    destination
+                };
+                asm.check(
+                    (-64..=63).contains(&offset),
+                    &format!("{} must target the first or last 64 bytes", base),
+                );
+                asm.emit(
+                    base,
+                    &[opcode, indirect | (offset & 0x7F) as u8],
+                    Some(destination),
+                );
+            }
+        }
+        true
+    }
+
+    fn fill(&self) -> u16 {
+        0xC0
+    }
+}
+
+impl S2650Assembler {
+    // Register R0-R3 or condition EQ/GT/LT/UN, which share the low bits
+    fn field(&self, asm: &Assembly, base: &str, f: &str) -> u8 {
+        match f {
+            "R0" | "EQ" | "Z" | "0" => 0,
+            "R1" | "GT" | "P" | "1" => 1,
+            "R2" | "LT" | "N" | "2" => 2,
+            "R3" | "UN" | "3" => 3,
+            _ => asm.error(&format!("invalid register or condition {} for {}", f, base)),
+        }
+    }
+
+    fn index(&self, asm: &Assembly, base: &str, e: &Expression) -> u8 {
+        match e {
+            Expression::Identifier(x) => self.field(asm, base, x),
+            _ => asm.error(&format!("invalid index for {}", base)),
+        }
+    }
+
+    // Address, with the indirect bit set when it's prefixed with '*'
+    fn address(&self, asm: &Assembly, base: &str, e: &Expression) -> (u8, i64) {
+        let (indirect, e) = match e {
+            Expression::Deferred(e) => (0x80, e.as_ref()),
+            e => (0x00, e),
+        };
+        let a = asm.value(e);
+        asm.check(
+            (0..ADDRESS_SPACE).contains(&a),
+            &format!("invalid address for {}", base),
+        );
+        (indirect, a & (ADDRESS_SPACE - 1))
+    }
+}
@@ -0,0 +1,839 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// 6502 simulator, to run assembled code without an emulator
+//
+// The image is loaded into 64K of RAM, without any hardware behind it.
+// A routine runs from its address until BRK, until the RTS that returns
+// from it, or until a cycle limit. Cycles are those of the NMOS 6502,
+// with the extra cycles for crossing pages and taking branches.
+//
+// The opcodes are decoded from the tables of the assembler, so each
+// variant runs what it can assemble: decimal mode is ignored on the
+// 2A03, and JMP (address) only has its page bug on NMOS chips. The
+// unstable undocumented opcodes stop a routine as unsupported, and the
+// 65816 isn't simulated.
+//
+// test "name" starts a test block, made of set, call and expect
+// directives that asm7x test runs in order on a fresh CPU:
+// set A=5, mem[$10]=3 before calling a routine with call label, and
+// expect A=8, flag C=0, mem[$20..$22]=[1,2,3] after it returns.
+
+use crate::mos6502::{cycles, page_penalty, unstable, Mode, Mos6502Assembler, Variant};
+use crate::output::Image;
+use crate::{Assembly, Expression, Instruction};
+
+const C: u8 = 0x01;
+const Z: u8 = 0x02;
+const I: u8 = 0x04;
+const D: u8 = 0x08;
+const B: u8 = 0x10;
+const U: u8 = 0x20;
+const V: u8 = 0x40;
+const N: u8 = 0x80;
+
+// Why a routine stopped
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    Brk,
+    Rts,
+    Limit,
+    // Opcode that the simulator can't run
+    Unsupported(u8),
+}
+
+const REGISTERS: [&str; 6] = ["A", "X", "Y", "S", "P", "PC"];
+
+const FLAGS: [(&str, u8); 6] = [("N", N), ("V", V), ("D", D), ("I", I), ("Z", Z), ("C", C)];
+
+// Cycles after which a call gives up, unless given
+const CALL_LIMIT: i64 = 1_000_000;
+
+// What set and expect assign: a register, a flag, or memory from an
+// address
+pub enum Target {
+    Register(&'static str),
+    Flag(&'static str, u8),
+    Memory(u16),
+}
+
+pub enum Step {
+    Set(Target, Vec<u16>),
+    // Line, address and cycle limit
+    Call(u32, u16, u64),
+    // Line, then what's expected
+    Expect(u32, Target, Vec<u16>),
+}
+
+// A test block, from test "name" to the next one
+pub struct Test {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+// Handle the test, set, call and expect directives, which collect the
+// tests without emitting anything
+pub fn directive(asm: &mut Assembly, i: &Instruction) -> bool {
+    match i.mnemonic.as_str() {
+        "test" => {
+            let name = match asm.parameter(i) {
+                Expression::Text(t) => t.clone(),
+                _ => asm.error("invalid parameter for test"),
+            };
+            if asm.tests.iter().any(|t| t.name == name) {
+                asm.error(&format!("duplicate test: {}", name));
+            }
+            asm.tests.push(Test {
+                name,
+                steps: Vec::new(),
+            });
+        }
+        "set" | "expect" | "call" if asm.tests.is_empty() => {
+            asm.error(&format!("{} outside of a test", i.mnemonic))
+        }
+        "set" | "expect" => {
+            if i.parameters.is_empty() {
+                asm.error(&format!("missing parameter for {}", i.mnemonic));
+            }
+            for p in &i.parameters {
+                let (target, values) = match p {
+                    Expression::Assignment(t, v) => assignment(asm, t, v),
+                    _ => asm.error(&format!("invalid parameter for {}", i.mnemonic)),
+                };
+                let step = match i.mnemonic.as_str() {
+                    "set" => Step::Set(target, values),
+                    _ => Step::Expect(asm.line, target, values),
+                };
+                asm.tests.last_mut().unwrap().steps.push(step);
+            }
+        }
+        "call" => {
+            let (address, limit) = match i.parameters.as_slice() {
+                [a] => (asm.value(a), CALL_LIMIT),
+                [a, l] => (asm.value(a), asm.value(l)),
+                _ => asm.error("invalid parameters for call"),
+            };
+            asm.check((0..=0xFFFF).contains(&address), "invalid address for call");
+            asm.check(limit > 0, "invalid cycle limit for call");
+            let step = Step::Call(asm.line, address as u16, limit as u64);
+            asm.tests.last_mut().unwrap().steps.push(step);
+        }
+        _ => return false,
+    }
+    true
+}
+
+// The target of set or expect, and its values, where one value fills a
+// range of memory
+fn assignment(asm: &Assembly, target: &Expression, value: &Expression) -> (Target, Vec<u16>) {
+    let (target, count) = match target {
+        Expression::Identifier(r) => match REGISTERS.iter().find(|n| *n == r) {
+            Some(r) => (Target::Register(r), 1),
+            None => asm.error(&format!("unknown register: {}", r)),
+        },
+        Expression::Qualified(q, f) if q == "flag" => match f.as_ref() {
+            Expression::Identifier(f) => match FLAGS.iter().find(|(n, _)| n == f) {
+                Some((n, bit)) => (Target::Flag(n, *bit), 1),
+                None => asm.error(&format!("unknown flag: {}", f)),
+            },
+            _ => asm.error("invalid flag"),
+        },
+        Expression::Qualified(q, m) if q == "mem" => match m.as_ref() {
+            Expression::Memory(r) => match r.as_ref() {
+                Expression::Range(a, b) => {
+                    let (a, b) = (asm.value(a), asm.value(b));
+                    if !(0..=0xFFFF).contains(&a) || !(a..=0xFFFF).contains(&b) {
+                        asm.error("invalid memory range");
+                    }
+                    (Target::Memory(a as u16), (b - a + 1) as usize)
+                }
+                a => {
+                    let a = asm.value(a);
+                    if !(0..=0xFFFF).contains(&a) {
+                        asm.error("invalid memory address");
+                    }
+                    (Target::Memory(a as u16), 1)
+                }
+            },
+            _ => asm.error("invalid memory"),
+        },
+        _ => asm.error("expected register, flag or memory"),
+    };
+    let mut values: Vec<i64> = match value {
+        Expression::List(l) => l.iter().map(|e| asm.value(e)).collect(),
+        e => vec![asm.value(e)],
+    };
+    if values.len() == 1 {
+        values.resize(count, values[0]);
+    }
+    asm.check(
+        values.len() == count,
+        &format!("{} values needed for {}", count, describe(&target, count)),
+    );
+    let range = match target {
+        Target::Register("PC") => 0..=0xFFFF,
+        Target::Flag(..) => 0..=1,
+        _ => -128..=255,
+    };
+    for v in &values {
+        asm.check(
+            range.contains(v),
+            &format!("invalid value for {}", describe(&target, count)),
+        );
+    }
+    let mask = match target {
+        Target::Register("PC") => 0xFFFF,
+        _ => 0xFF,
+    };
+    let values = values.iter().map(|v| (v & mask) as u16).collect();
+    (target, values)
+}
+
+fn describe(target: &Target, count: usize) -> String {
+    match target {
+        Target::Register(r) => String::from(*r),
+        Target::Flag(f, _) => format!("flag {}", f),
+        Target::Memory(a) if count > 1 => {
+            format!("mem[${:04X}..${:04X}]", a, a.wrapping_add(count as u16 - 1))
+        }
+        Target::Memory(a) => format!("mem[${:04X}]", a),
+    }
+}
+
+fn show(target: &Target, values: &[u16]) -> String {
+    let values: Vec<String> = values
+        .iter()
+        .map(|v| match target {
+            Target::Flag(..) => format!("{}", v),
+            Target::Register("PC") => format!("${:04X}", v),
+            _ => format!("${:02X}", v),
+        })
+        .collect();
+    match values.as_slice() {
+        [v] => v.clone(),
+        _ => format!("[{}]", values.join(",")),
+    }
+}
+
+impl Test {
+    // Run the steps on a fresh CPU, up to the first failure
+    pub fn run(&self, image: &Image, variant: Variant) -> Result<(), String> {
+        let mut cpu = Cpu::new(variant);
+        cpu.load(image);
+        for step in &self.steps {
+            match step {
+                Step::Set(target, values) => cpu.set(target, values),
+                Step::Call(line, address, limit) => match cpu.call(*address, *limit) {
+                    Stop::Brk | Stop::Rts => (),
+                    Stop::Limit => {
+                        return Err(format!("line {}: no return after {} cycles", line, limit))
+                    }
+                    Stop::Unsupported(o) => {
+                        return Err(format!(
+                            "line {}: unsupported opcode ${:02X} at ${:04X}",
+                            line, o, cpu.pc
+                        ))
+                    }
+                },
+                Step::Expect(line, target, values) => {
+                    let actual = cpu.get(target, values.len());
+                    if actual != *values {
+                        return Err(format!(
+                            "line {}: {} is {}, expected {}",
+                            line,
+                            describe(target, values.len()),
+                            show(target, &actual),
+                            show(target, values)
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    None,
+    Accumulator,
+    Value(u8),
+    Address(u16),
+    // BBR and BBS, a zero page address and a branch target
+    Branch(u16, u16),
+}
+
+pub struct Cpu {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    // Stack pointer, in page 1
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+    pub memory: Vec<u8>,
+    pub cycles: u64,
+    // Mnemonic and addressing mode of each opcode that can be run
+    opcodes: [Option<(&'static str, Mode)>; 256],
+    // Whether the D flag changes ADC and SBC, which it doesn't on the
+    // 2A03
+    decimal: bool,
+    cmos: bool,
+}
+
+impl Cpu {
+    pub fn new(variant: Variant) -> Cpu {
+        let decoder = Mos6502Assembler::new(variant);
+        Cpu {
+            a: 0,
+            x: 0,
+            y: 0,
+            s: 0xFD,
+            p: U | I,
+            pc: 0,
+            memory: vec![0; 0x10000],
+            cycles: 0,
+            opcodes: std::array::from_fn(|o| {
+                Some(o as u8)
+                    .filter(|o| !unstable(*o))
+                    .and_then(|o| decoder.opcode(o))
+            }),
+            decimal: variant != Variant::Ricoh,
+            cmos: matches!(variant, Variant::Cmos | Variant::Rockwell),
+        }
+    }
+
+    // Copy each block of an image at its address, without the header
+    pub fn load(&mut self, image: &Image) {
+        for (n, &(address, offset, _)) in image.sections.iter().enumerate() {
+            let end = image.sections.get(n + 1).map_or(image.bytes.len(), |s| s.1);
+            for (i, b) in image.bytes[offset..end].iter().enumerate() {
+                self.memory[(address as usize + i) & 0xFFFF] = *b;
+            }
+        }
+    }
+
+    // Run a routine until BRK, until its final RTS, or until it has
+    // taken a number of cycles
+    pub fn call(&mut self, address: u16, limit: u64) -> Stop {
+        self.pc = address;
+        let start = self.cycles;
+        let stack = self.s;
+        loop {
+            if self.cycles - start >= limit {
+                return Stop::Limit;
+            }
+            // Nothing was pushed by the routine, so this RTS leaves it
+            if self.read(self.pc) == 0x60 && self.s == stack {
+                self.cycles += 6;
+                return Stop::Rts;
+            }
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+        }
+    }
+
+    // Run one instruction, or tell why it can't be run
+    pub fn step(&mut self) -> Option<Stop> {
+        let opcode = self.read(self.pc);
+        let (m, mode) = match self.opcodes[opcode as usize] {
+            Some(d) => d,
+            None => return Some(Stop::Unsupported(opcode)),
+        };
+        let cycles = match cycles(m, mode) {
+            Some(c) => c,
+            None => return Some(Stop::Unsupported(opcode)),
+        };
+        let (operand, crossed) = self.operand(mode);
+        self.cycles += u64::from(cycles);
+        if crossed && page_penalty(m, mode) {
+            self.cycles += 1;
+        }
+        if m == "BRK" {
+            return Some(Stop::Brk);
+        }
+        if !self.execute(m, operand) {
+            return Some(Stop::Unsupported(opcode));
+        }
+        None
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    // Assign a register, a flag or memory
+    fn set(&mut self, target: &Target, values: &[u16]) {
+        match target {
+            Target::Register(r) => {
+                let v = values[0];
+                match *r {
+                    "A" => self.a = v as u8,
+                    "X" => self.x = v as u8,
+                    "Y" => self.y = v as u8,
+                    "S" => self.s = v as u8,
+                    "P" => self.p = v as u8 | U,
+                    _ => self.pc = v,
+                }
+            }
+            Target::Flag(_, bit) => self.flag(*bit, values[0] != 0),
+            Target::Memory(a) => {
+                for (n, v) in values.iter().enumerate() {
+                    self.write(a.wrapping_add(n as u16), *v as u8);
+                }
+            }
+        }
+    }
+
+    // Read a register, a flag or memory
+    fn get(&self, target: &Target, count: usize) -> Vec<u16> {
+        match target {
+            Target::Register(r) => vec![match *r {
+                "A" => u16::from(self.a),
+                "X" => u16::from(self.x),
+                "Y" => u16::from(self.y),
+                "S" => u16::from(self.s),
+                "P" => u16::from(self.p),
+                _ => self.pc,
+            }],
+            Target::Flag(_, bit) => vec![u16::from(self.p & bit != 0)],
+            Target::Memory(a) => (0..count)
+                .map(|n| u16::from(self.read(a.wrapping_add(n as u16))))
+                .collect(),
+        }
+    }
+
+    // Little-endian word
+    pub fn word(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read(address), self.read(address.wrapping_add(1))])
+    }
+
+    // Pointer in the zero page, which wraps around within it
+    fn zero_page_word(&self, address: u8) -> u16 {
+        u16::from_le_bytes([
+            self.read(u16::from(address)),
+            self.read(u16::from(address.wrapping_add(1))),
+        ])
+    }
+
+    // Decode the operand of the instruction at PC, and move PC past
+    // it, along with whether indexing crossed a page
+    fn operand(&mut self, mode: Mode) -> (Operand, bool) {
+        use Mode::*;
+
+        let pc = self.pc;
+        let byte = self.read(pc.wrapping_add(1));
+        let word = self.word(pc.wrapping_add(1));
+        let indexed = |base: u16, index: u8| {
+            let address = base.wrapping_add(u16::from(index));
+            (Operand::Address(address), address & 0xFF00 != base & 0xFF00)
+        };
+        let ((operand, crossed), size) = match mode {
+            Implied => ((Operand::None, false), 1),
+            Accumulator => ((Operand::Accumulator, false), 1),
+            Immediate => ((Operand::Value(byte), false), 2),
+            ZeroPage => ((Operand::Address(u16::from(byte)), false), 2),
+            ZeroPageX => {
+                let address = u16::from(byte.wrapping_add(self.x));
+                ((Operand::Address(address), false), 2)
+            }
+            ZeroPageY => {
+                let address = u16::from(byte.wrapping_add(self.y));
+                ((Operand::Address(address), false), 2)
+            }
+            Absolute => ((Operand::Address(word), false), 3),
+            AbsoluteX => (indexed(word, self.x), 3),
+            AbsoluteY => (indexed(word, self.y), 3),
+            Indirect => {
+                // The NMOS 6502 doesn't carry into the high byte
+                let high = if self.cmos {
+                    word.wrapping_add(1)
+                } else {
+                    (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF)
+                };
+                let address = u16::from_le_bytes([self.read(word), self.read(high)]);
+                ((Operand::Address(address), false), 3)
+            }
+            IndirectX => {
+                let address = self.zero_page_word(byte.wrapping_add(self.x));
+                ((Operand::Address(address), false), 2)
+            }
+            IndirectY => (indexed(self.zero_page_word(byte), self.y), 2),
+            ZeroPageIndirect => ((Operand::Address(self.zero_page_word(byte)), false), 2),
+            AbsoluteIndirectX => {
+                let address = self.word(word.wrapping_add(u16::from(self.x)));
+                ((Operand::Address(address), false), 3)
+            }
+            Relative => {
+                let target = pc.wrapping_add(2).wrapping_add(byte as i8 as u16);
+                ((Operand::Address(target), false), 2)
+            }
+            ZeroPageRelative => {
+                let offset = self.read(pc.wrapping_add(2)) as i8 as u16;
+                let target = pc.wrapping_add(3).wrapping_add(offset);
+                ((Operand::Branch(u16::from(byte), target), false), 3)
+            }
+            // The 65816 modes have no cycle counts, and never get here
+            _ => ((Operand::None, false), 1),
+        };
+        self.pc = pc.wrapping_add(size);
+        (operand, crossed)
+    }
+
+    fn load_operand(&self, operand: Operand) -> u8 {
+        match operand {
+            Operand::Accumulator => self.a,
+            Operand::Value(v) => v,
+            Operand::Address(a) => self.read(a),
+            Operand::None | Operand::Branch(..) => 0,
+        }
+    }
+
+    fn store(&mut self, operand: Operand, value: u8) {
+        match operand {
+            Operand::Accumulator => self.a = value,
+            Operand::Address(a) => self.write(a, value),
+            _ => (),
+        }
+    }
+
+    // Read an operand, change it, and write it back
+    fn modify(&mut self, operand: Operand, f: impl Fn(&mut Cpu, u8) -> u8) -> u8 {
+        let v = self.load_operand(operand);
+        let v = f(self, v);
+        self.store(operand, v);
+        v
+    }
+
+    fn flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn nz(&mut self, v: u8) -> u8 {
+        self.flag(Z, v == 0);
+        self.flag(N, v & 0x80 != 0);
+        v
+    }
+
+    fn push(&mut self, v: u8) {
+        self.write(0x0100 | u16::from(self.s), v);
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        self.read(0x0100 | u16::from(self.s))
+    }
+
+    // A taken branch takes one more cycle, and two when it crosses a
+    // page
+    fn branch(&mut self, taken: bool, operand: Operand) {
+        let target = match operand {
+            Operand::Address(a) | Operand::Branch(_, a) => a,
+            _ => return,
+        };
+        if taken {
+            self.cycles += if target & 0xFF00 != self.pc & 0xFF00 {
+                2
+            } else {
+                1
+            };
+            self.pc = target;
+        }
+    }
+
+    fn compare(&mut self, register: u8, v: u8) {
+        self.flag(C, register >= v);
+        self.nz(register.wrapping_sub(v));
+    }
+
+    // Binary addition with carry
+    fn add(&mut self, v: u8) {
+        let sum = u16::from(self.a) + u16::from(v) + u16::from(self.p & C);
+        let r = sum as u8;
+        self.flag(V, (self.a ^ r) & (v ^ r) & 0x80 != 0);
+        self.flag(C, sum > 0xFF);
+        self.a = self.nz(r);
+    }
+
+    // In decimal mode, the NMOS 6502 sets N, V and Z from intermediate
+    // results, which the CMOS chips fix for N and Z
+    fn adc(&mut self, v: u8) {
+        if self.p & D == 0 || !self.decimal {
+            return self.add(v);
+        }
+        let carry = u16::from(self.p & C);
+        let binary = (u16::from(self.a) + u16::from(v) + carry) as u8;
+        let mut low = u16::from(self.a & 0x0F) + u16::from(v & 0x0F) + carry;
+        let mut high = u16::from(self.a >> 4) + u16::from(v >> 4);
+        if low > 9 {
+            low += 6;
+        }
+        if low > 0x0F {
+            high += 1;
+        }
+        let intermediate = (high << 4) as u8;
+        self.flag(Z, binary == 0);
+        self.flag(N, intermediate & 0x80 != 0);
+        self.flag(V, (self.a ^ intermediate) & !(self.a ^ v) & 0x80 != 0);
+        if high > 9 {
+            high += 6;
+        }
+        self.flag(C, high > 0x0F);
+        self.a = ((high << 4) | (low & 0x0F)) as u8;
+        if self.cmos {
+            self.nz(self.a);
+        }
+    }
+
+    // In decimal mode, the flags come from the binary subtraction on
+    // the NMOS 6502
+    fn sbc(&mut self, v: u8) {
+        if self.p & D == 0 || !self.decimal {
+            return self.add(!v);
+        }
+        let borrow = i16::from(1 - (self.p & C));
+        let mut low = i16::from(self.a & 0x0F) - i16::from(v & 0x0F) - borrow;
+        let mut high = i16::from(self.a >> 4) - i16::from(v >> 4);
+        if low < 0 {
+            low -= 6;
+            high -= 1;
+        }
+        if high < 0 {
+            high -= 6;
+        }
+        self.add(!v);
+        self.a = ((high << 4) | (low & 0x0F)) as u8;
+        if self.cmos {
+            self.nz(self.a);
+        }
+    }
+
+    fn asl(&mut self, v: u8) -> u8 {
+        self.flag(C, v & 0x80 != 0);
+        self.nz(v << 1)
+    }
+
+    fn lsr(&mut self, v: u8) -> u8 {
+        self.flag(C, v & 0x01 != 0);
+        self.nz(v >> 1)
+    }
+
+    fn rol(&mut self, v: u8) -> u8 {
+        let carry = self.p & C;
+        self.flag(C, v & 0x80 != 0);
+        self.nz((v << 1) | carry)
+    }
+
+    fn ror(&mut self, v: u8) -> u8 {
+        let carry = self.p & C;
+        self.flag(C, v & 0x01 != 0);
+        self.nz((v >> 1) | (carry << 7))
+    }
+
+    // Run a decoded instruction, or return false for one that can't be
+    // run
+    fn execute(&mut self, m: &str, operand: Operand) -> bool {
+        let value = self.load_operand(operand);
+        match m {
+            "LDA" => self.a = self.nz(value),
+            "LDX" => self.x = self.nz(value),
+            "LDY" => self.y = self.nz(value),
+            "STA" => self.store(operand, self.a),
+            "STX" => self.store(operand, self.x),
+            "STY" => self.store(operand, self.y),
+            "STZ" => self.store(operand, 0),
+            "TAX" => self.x = self.nz(self.a),
+            "TAY" => self.y = self.nz(self.a),
+            "TXA" => self.a = self.nz(self.x),
+            "TYA" => self.a = self.nz(self.y),
+            "TSX" => self.x = self.nz(self.s),
+            "TXS" => self.s = self.x,
+            "ADC" => self.adc(value),
+            "SBC" => self.sbc(value),
+            "AND" => self.a = self.nz(self.a & value),
+            "ORA" => self.a = self.nz(self.a | value),
+            "EOR" => self.a = self.nz(self.a ^ value),
+            "CMP" => self.compare(self.a, value),
+            "CPX" => self.compare(self.x, value),
+            "CPY" => self.compare(self.y, value),
+            "BIT" => {
+                self.flag(Z, self.a & value == 0);
+                // BIT #value only changes Z
+                if !matches!(operand, Operand::Value(_)) {
+                    self.flag(N, value & N != 0);
+                    self.flag(V, value & V != 0);
+                }
+            }
+            "INC" => _ = self.modify(operand, |c, v| c.nz(v.wrapping_add(1))),
+            "DEC" => _ = self.modify(operand, |c, v| c.nz(v.wrapping_sub(1))),
+            "INX" => self.x = self.nz(self.x.wrapping_add(1)),
+            "INY" => self.y = self.nz(self.y.wrapping_add(1)),
+            "DEX" => self.x = self.nz(self.x.wrapping_sub(1)),
+            "DEY" => self.y = self.nz(self.y.wrapping_sub(1)),
+            "ASL" => _ = self.modify(operand, Cpu::asl),
+            "LSR" => _ = self.modify(operand, Cpu::lsr),
+            "ROL" => _ = self.modify(operand, Cpu::rol),
+            "ROR" => _ = self.modify(operand, Cpu::ror),
+            "TSB" => {
+                let a = self.a;
+                self.modify(operand, |c, v| {
+                    c.flag(Z, a & v == 0);
+                    v | a
+                });
+            }
+            "TRB" => {
+                let a = self.a;
+                self.modify(operand, |c, v| {
+                    c.flag(Z, a & v == 0);
+                    v & !a
+                });
+            }
+            "JMP" => {
+                if let Operand::Address(a) = operand {
+                    self.pc = a;
+                }
+            }
+            "JSR" => {
+                let [low, high] = self.pc.wrapping_sub(1).to_le_bytes();
+                self.push(high);
+                self.push(low);
+                self.execute("JMP", operand);
+            }
+            "RTS" => {
+                let low = self.pull();
+                let high = self.pull();
+                self.pc = u16::from_le_bytes([low, high]).wrapping_add(1);
+            }
+            "RTI" => {
+                self.p = (self.pull() | U) & !B;
+                let low = self.pull();
+                let high = self.pull();
+                self.pc = u16::from_le_bytes([low, high]);
+            }
+            "BPL" => self.branch(self.p & N == 0, operand),
+            "BMI" => self.branch(self.p & N != 0, operand),
+            "BVC" => self.branch(self.p & V == 0, operand),
+            "BVS" => self.branch(self.p & V != 0, operand),
+            "BCC" => self.branch(self.p & C == 0, operand),
+            "BCS" => self.branch(self.p & C != 0, operand),
+            "BNE" => self.branch(self.p & Z == 0, operand),
+            "BEQ" => self.branch(self.p & Z != 0, operand),
+            "BRA" => self.branch(true, operand),
+            "CLC" => self.flag(C, false),
+            "SEC" => self.flag(C, true),
+            "CLI" => self.flag(I, false),
+            "SEI" => self.flag(I, true),
+            "CLD" => self.flag(D, false),
+            "SED" => self.flag(D, true),
+            "CLV" => self.flag(V, false),
+            "PHA" => self.push(self.a),
+            "PHX" => self.push(self.x),
+            "PHY" => self.push(self.y),
+            "PHP" => self.push(self.p | B | U),
+            "PLA" => {
+                let v = self.pull();
+                self.a = self.nz(v);
+            }
+            "PLX" => {
+                let v = self.pull();
+                self.x = self.nz(v);
+            }
+            "PLY" => {
+                let v = self.pull();
+                self.y = self.nz(v);
+            }
+            "PLP" => self.p = (self.pull() | U) & !B,
+            "NOP" => (),
+            // Rockwell bit instructions, with the bit number in the
+            // mnemonic
+            _ if m.len() == 4 && ["RMB", "SMB", "BBR", "BBS"].contains(&&m[..3]) => {
+                let bit = 1 << (m.as_bytes()[3] - b'0');
+                match (&m[..3], operand) {
+                    ("RMB", _) => _ = self.modify(operand, |_, v| v & !bit),
+                    ("SMB", _) => _ = self.modify(operand, |_, v| v | bit),
+                    ("BBR", Operand::Branch(a, _)) => self.branch(self.read(a) & bit == 0, operand),
+                    ("BBS", Operand::Branch(a, _)) => self.branch(self.read(a) & bit != 0, operand),
+                    _ => return false,
+                }
+            }
+            // Stable undocumented opcodes
+            "LAX" => {
+                self.a = self.nz(value);
+                self.x = self.a;
+            }
+            "SAX" => self.store(operand, self.a & self.x),
+            "DCP" => {
+                let r = self.modify(operand, |_, v| v.wrapping_sub(1));
+                self.compare(self.a, r);
+            }
+            "ISC" => {
+                let r = self.modify(operand, |_, v| v.wrapping_add(1));
+                self.sbc(r);
+            }
+            "SLO" => {
+                let r = self.modify(operand, Cpu::asl);
+                self.a = self.nz(self.a | r);
+            }
+            "RLA" => {
+                let r = self.modify(operand, Cpu::rol);
+                self.a = self.nz(self.a & r);
+            }
+            "SRE" => {
+                let r = self.modify(operand, Cpu::lsr);
+                self.a = self.nz(self.a ^ r);
+            }
+            "RRA" => {
+                let r = self.modify(operand, Cpu::ror);
+                self.adc(r);
+            }
+            "ANC" => {
+                self.a = self.nz(self.a & value);
+                self.flag(C, self.a & 0x80 != 0);
+            }
+            "ALR" => {
+                let r = self.a & value;
+                self.a = self.lsr(r);
+            }
+            "ARR" => {
+                let carry = self.p & C;
+                self.a = self.nz(((self.a & value) >> 1) | (carry << 7));
+                self.flag(C, self.a & 0x40 != 0);
+                self.flag(V, ((self.a >> 6) ^ (self.a >> 5)) & 1 != 0);
+            }
+            "SBX" => {
+                let ax = self.a & self.x;
+                self.flag(C, ax >= value);
+                self.x = self.nz(ax.wrapping_sub(value));
+            }
+            "LAS" => {
+                let r = self.nz(value & self.s);
+                self.a = r;
+                self.x = r;
+                self.s = r;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
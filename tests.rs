@@ -0,0 +1,327 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Tests, built with rustc --edition 2021 --test asm7x.rs
+//
+// Each tests/name.asm is assembled and compared against the golden
+// binary tests/name.bin, header included. The 6502 family sources
+// also go through the disassembler and back.
+
+use std::path::{Path, PathBuf};
+
+use crate::mos6502::{Mos6502Assembler, Variant};
+use crate::output::Image;
+use crate::simulator::{Cpu, Stop};
+
+fn corpus() -> PathBuf {
+    Path::new(file!()).with_file_name("tests")
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    match std::fs::read(path) {
+        Ok(b) => b,
+        Err(e) => panic!("cannot read {}: {}", path.display(), e),
+    }
+}
+
+// Assemble a source from the corpus
+fn assemble(name: &str) -> Image {
+    let path = corpus().join(format!("{}.asm", name));
+    let source = String::from_utf8(read(&path)).unwrap();
+    crate::assemble(&source, &path.to_string_lossy())
+}
+
+// Disassemble some bytes, and assemble them back
+fn round_trip(processor: &str, bytes: &[u8], origin: u32) -> Vec<u8> {
+    let variant = Variant::named(processor).unwrap();
+    let source = Mos6502Assembler::new(variant).disassemble(processor, bytes, origin);
+    crate::assemble(&source, "<disassembly>").bytes
+}
+
+#[test]
+fn golden() {
+    let mut sources: Vec<PathBuf> = std::fs::read_dir(corpus())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "asm"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty());
+    for source in sources {
+        let name = source.file_stem().unwrap().to_string_lossy();
+        let golden = read(&source.with_extension("bin"));
+        let image = assemble(&name);
+        assert!(
+            [image.header, image.bytes].concat() == golden,
+            "{} differs from its golden binary",
+            name
+        );
+    }
+}
+
+#[test]
+fn corpus_round_trip() {
+    for name in ["6502", "6502x", "r65c02", "65816"] {
+        let image = assemble(name);
+        assert!(
+            round_trip(name, &image.bytes, image.origin) == image.bytes,
+            "{} doesn't survive the disassembler",
+            name
+        );
+    }
+}
+
+#[test]
+fn random_round_trip() {
+    // Linear congruential generator, for the same bytes on each run
+    let mut seed: u32 = 1;
+    let bytes: Vec<u8> = (0..4096)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+    // Branches near the ends of memory wrap around
+    for processor in ["6502", "6502x", "2a03", "6507", "65c02", "r65c02", "65816"] {
+        for origin in [0x0000, 0x0020, 0xC000, 0xF000] {
+            assert!(
+                round_trip(processor, &bytes, origin) == bytes,
+                "random bytes at {:#06X} don't survive the disassembler on {}",
+                origin,
+                processor
+            );
+        }
+    }
+}
+
+// BPL used to be encoded as 0xD0, which is BNE
+#[test]
+fn bpl() {
+    let image = crate::assemble(" processor 6502\n org $1000\nLoop:\n BPL Loop\n", "<bpl>");
+    assert_eq!(image.bytes, [0x10, 0xFE]);
+}
+
+// 8x8 multiply, run in the simulator from its label to its RTS
+#[test]
+fn simulator() {
+    let source = concat!(
+        " processor 6502\n",
+        " org $0800\n",
+        "Multiply:\n LDA #0\n LDX #8\n LSR $10\n",
+        "Loop:\n BCC Skip\n CLC\n ADC $11\n",
+        "Skip:\n ROR A\n ROR $10\n DEX\n BNE Loop\n RTS\n",
+    );
+    let image = crate::assemble(source, "<multiply>");
+    let mut cpu = Cpu::new(Variant::Nmos);
+    cpu.load(&image);
+    cpu.write(0x10, 200);
+    cpu.write(0x11, 123);
+    let stop = cpu.call(image.symbols["Multiply"] as u16, 1000);
+    assert_eq!(stop, Stop::Rts);
+    assert_eq!(u16::from_le_bytes([cpu.read(0x10), cpu.a]), 200 * 123);
+    assert_eq!(cpu.cycles, 146);
+}
+
+// Decimal mode works on the 6502 but not on the 2A03
+#[test]
+fn simulator_decimal() {
+    for (processor, variant, result) in [
+        ("6502", Variant::Nmos, 0x47),
+        ("2a03", Variant::Ricoh, 0x41),
+    ] {
+        let source = format!(
+            " processor {}\n org $0800\n SED\n CLC\n LDA #$19\n ADC #$28\n BRK\n",
+            processor
+        );
+        let image = crate::assemble(&source, "<decimal>");
+        let mut cpu = Cpu::new(variant);
+        cpu.load(&image);
+        assert_eq!(cpu.call(0x0800, 1000), Stop::Brk);
+        assert_eq!(cpu.a, result, "{}", processor);
+    }
+}
+
+// The unstable undocumented opcodes stop the simulator
+#[test]
+fn simulator_unstable() {
+    let source = " processor 6502x\n org $0800\n LAX #12\n BRK\n";
+    let image = crate::assemble(source, "<unstable>");
+    let mut cpu = Cpu::new(Variant::Undocumented);
+    cpu.load(&image);
+    assert_eq!(cpu.call(0x0800, 1000), Stop::Unsupported(0xAB));
+}
+
+// Test blocks pass or fail on what the routine leaves behind
+#[test]
+fn test_blocks() {
+    let source = concat!(
+        " processor 65c02\n",
+        " org $0800\n",
+        "Copy:\n LDX #2\nLoop:\n LDA $30,X\n STA $20,X\n DEX\n BPL Loop\n RTS\n",
+        " test \"copy\"\n set mem[$30..$32]=[1,2,3]\n call Copy\n",
+        " expect mem[$20..$22]=[1,2,3], X=$FF, flag N=1\n",
+        " test \"wrong\"\n set mem[$30..$32]=7\n call Copy\n expect mem[$22]=8\n",
+        " test \"limit\"\n call Copy, 10\n",
+    );
+    let image = crate::assemble(source, "<tests>");
+    let results: Vec<bool> = image
+        .tests
+        .iter()
+        .map(|t| t.run(&image, Variant::Cmos).is_ok())
+        .collect();
+    assert_eq!(results, [true, false, false]);
+}
+
+// LDX #192 and one pass through the loop take up to 25 cycles
+const CYCLES: &str = concat!(
+    " processor 6502\n org $F000\n cycles_begin\n LDX #192\n",
+    "Line:\n STA $02\n LDA $1000,X\n LDA $10FF,X\n LDA ($80),Y\n DEX\n BNE Line\n",
+);
+
+#[test]
+fn cycles_end() {
+    crate::assemble(&format!("{} cycles_end 25\n", CYCLES), "<cycles>");
+}
+
+#[test]
+#[should_panic]
+fn cycles_end_over() {
+    crate::assemble(&format!("{} cycles_end 24\n", CYCLES), "<cycles>");
+}
+
+// Memory in set and expect is limited to 64K
+#[test]
+#[should_panic]
+fn test_memory_range() {
+    let source = " processor 6502\n org $0800\n test \"huge\"\n set mem[$10..$FFFFFFFFF]=1\n";
+    crate::assemble(source, "<range>");
+}
+
+#[test]
+#[should_panic]
+fn test_memory_address() {
+    let source = " processor 6502\n org $0800\n test \"above\"\n expect mem[$12345]=1\n";
+    crate::assemble(source, "<address>");
+}
+
+// SDBD must be right in front of the instruction that it applies to
+#[test]
+#[should_panic]
+fn sdbd_directive() {
+    let source = " processor cp1610\n org $5000\n SDBD\n decle 5\n MVII #$1234, R0\n";
+    crate::assemble(source, "<sdbd>");
+}
+
+#[test]
+#[should_panic]
+fn sdbd_org() {
+    let source = " processor cp1610\n org $5000\n SDBD\n org $5010\n MVII #$1234, R0\n";
+    crate::assemble(source, "<sdbd>");
+}
+
+// NES 2.0 RAM sizes are powers of two from 128 bytes
+#[test]
+#[should_panic]
+fn ines_ram_size() {
+    let source = " processor 2a03\n ines.prg 1\n ines.prgram 100\n org $C000\n";
+    crate::assemble(source, "<ines>");
+}
+
+// The header needs a PRG size
+#[test]
+#[should_panic]
+fn ines_prg() {
+    let source = " processor 2a03\n ines.mapper 1\n org $8000\n NOP\n";
+    crate::assemble(source, "<ines>");
+}
+
+// The image must have the size that the iNES header gives
+#[test]
+#[should_panic]
+fn ines_size() {
+    let source = " processor 2a03\n ines.prg 2\n ines.chr 1\n org $8000\n NOP\n";
+    crate::assemble(source, "<ines>");
+}
+
+// The 65816 can assemble code outside of bank 0
+#[test]
+fn org_65816() {
+    let image = crate::assemble(
+        " processor 65816\n org $18000\nStart:\n BRA Start\n JML Start\n",
+        "<org>",
+    );
+    assert_eq!(image.origin, 0x18000);
+    assert_eq!(image.bytes, [0x80, 0xFE, 0x5C, 0x00, 0x80, 0x01]);
+}
+
+// Jumps and calls within the bank of the code use 16-bit addresses
+#[test]
+fn org_65816_bank() {
+    let image = crate::assemble(
+        " processor 65816\n org $808000\nStart:\n NOP\n JMP Start\n JSR Start\n",
+        "<org>",
+    );
+    assert_eq!(image.origin, 0x808000);
+    assert_eq!(image.bytes, [0xEA, 0x4C, 0x00, 0x80, 0x20, 0x00, 0x80]);
+}
+
+// A jump to another bank needs JML
+#[test]
+#[should_panic]
+fn org_65816_other_bank() {
+    crate::assemble(" processor 65816\n org $808000\n JMP $818000\n", "<org>");
+}
+
+// The 8086 can assemble code above 64K, with jumps in its segment
+#[test]
+fn org_8086() {
+    let image = crate::assemble(
+        " processor 8086\n org $12345\nStart:\n NOP\n JMP Start\n CALL Start\n",
+        "<org>",
+    );
+    assert_eq!(image.origin, 0x12345);
+    assert_eq!(image.bytes, [0x90, 0xEB, 0xFD, 0xE8, 0xFA, 0xFF]);
+}
+
+// Banks default to the windows of their bank switching scheme
+#[test]
+fn bank_windows() {
+    for (scheme, banks, window) in [
+        ("F8", " bank 0\n bank 1\n", 0xF000),
+        ("3F", " bank 0\n bank 1, $F800\n", 0xF800),
+        ("FE", " bank 0\n bank 1\n", 0xD000),
+        (
+            "E0",
+            " bank 0, $F000\n bank 1, $F000\n bank 2, $F000\n bank 3, $F000\n bank 4, $F000\n bank 5, $F000\n bank 6, $F400\n bank 7\n",
+            0xFC00,
+        ),
+    ] {
+        let source = format!(" processor 6507\n bankswitch {}\n{}Last:\n", scheme, banks);
+        let image = crate::assemble(&source, "<banks>");
+        assert_eq!(image.symbols["Last"], window, "{}", scheme);
+    }
+}
+
+// Negative immediate values are written in two's complement
+#[test]
+fn negative_immediate() {
+    let image = crate::assemble(
+        " processor 65816\n org $1000\nA1:\nA2:\n LDA #-1\n LDX #A1-A2-2\n REP #$30\n LDA #-2\n",
+        "<immediate>",
+    );
+    assert_eq!(
+        image.bytes,
+        [0xA9, 0xFF, 0xA2, 0xFE, 0xC2, 0x30, 0xA9, 0xFE, 0xFF]
+    );
+}
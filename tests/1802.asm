@@ -0,0 +1,18 @@
+ processor 1802
+ org 512
+Start: LDI 16
+ PHI R3
+ LDI #7
+ PLO 3
+ SEP R3
+Loop: GLO RF
+ BNZ Loop
+ LBR Far
+ OUT 4
+ INP 1
+ LDN 5
+ SEX R2
+ BR Next
+Next: SKP
+ org 1024
+Far: IDL
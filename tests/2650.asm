@@ -0,0 +1,26 @@
+ processor 2650
+ org 0
+ BCTA,UN Start
+ org $20
+Start: LODI,R0 $12
+ LODZ R1
+ STRZ,R2
+ EORZ R0
+ LODA,R1 Table
+ LODA,R0 Table,R2
+ STRA,R0 Table,R3+
+ LODA,R0 *Table,R1-
+ LODR,R2 Table
+ BCTR,EQ Start
+ BCFR,GT *Start
+ BDRR,R3 Start
+ BSTA,UN Sub
+ ZBSR $1FF0
+ BXA Table,R3
+ PPSL $10
+ RRR,R1
+ REDE,R0 $80
+ TMI,R1 $0F
+ HALT
+Sub: RETC,UN
+Table: NOP
@@ -0,0 +1,13 @@
+ processor 2a03
+ ines.prg 2
+ ines.chr 0
+ ines.mapper 4
+ ines.submapper 1
+ ines.prgnvram 8192
+ ines.chrram 8192
+ ines.battery
+ ines.mirroring horizontal
+ org $8000
+ NOP
+ org $FFFA
+ byte 0, 128, 0, 128, 0, 128
@@ -0,0 +1,162 @@
+ processor 6502
+ org $C000
+Start:
+	BRK
+	ORA	($12,X)
+	ORA	$12
+	ASL	$12
+	PHP
+	ORA	#$12
+	ASL	A
+	ORA	$1234
+	ASL	$1234
+BPL_:
+	BPL	BPL_
+	ORA	($12),Y
+	ORA	$12,X
+	ASL	$12,X
+	CLC
+	ORA	$1234,Y
+	ORA	$1234,X
+	ASL	$1234,X
+	JSR	$1234
+	AND	($12,X)
+	BIT	$12
+	AND	$12
+	ROL	$12
+	PLP
+	AND	#$12
+	ROL	A
+	BIT	$1234
+	AND	$1234
+	ROL	$1234
+BMI_:
+	BMI	BMI_
+	AND	($12),Y
+	AND	$12,X
+	ROL	$12,X
+	SEC
+	AND	$1234,Y
+	AND	$1234,X
+	ROL	$1234,X
+	RTI
+	EOR	($12,X)
+	EOR	$12
+	LSR	$12
+	PHA
+	EOR	#$12
+	LSR	A
+	JMP	$1234
+	EOR	$1234
+	LSR	$1234
+BVC_:
+	BVC	BVC_
+	EOR	($12),Y
+	EOR	$12,X
+	LSR	$12,X
+	CLI
+	EOR	$1234,Y
+	EOR	$1234,X
+	LSR	$1234,X
+	RTS
+	ADC	($12,X)
+	ADC	$12
+	ROR	$12
+	PLA
+	ADC	#$12
+	ROR	A
+	JMP	($1234)
+	ADC	$1234
+	ROR	$1234
+BVS_:
+	BVS	BVS_
+	ADC	($12),Y
+	ADC	$12,X
+	ROR	$12,X
+	SEI
+	ADC	$1234,Y
+	ADC	$1234,X
+	ROR	$1234,X
+	STA	($12,X)
+	STY	$12
+	STA	$12
+	STX	$12
+	DEY
+	TXA
+	STY	$1234
+	STA	$1234
+	STX	$1234
+BCC_:
+	BCC	BCC_
+	STA	($12),Y
+	STY	$12,X
+	STA	$12,X
+	STX	$12,Y
+	TYA
+	STA	$1234,Y
+	TXS
+	STA	$1234,X
+	LDY	#$12
+	LDA	($12,X)
+	LDX	#$12
+	LDY	$12
+	LDA	$12
+	LDX	$12
+	TAY
+	LDA	#$12
+	TAX
+	LDY	$1234
+	LDA	$1234
+	LDX	$1234
+BCS_:
+	BCS	BCS_
+	LDA	($12),Y
+	LDY	$12,X
+	LDA	$12,X
+	LDX	$12,Y
+	CLV
+	LDA	$1234,Y
+	TSX
+	LDY	$1234,X
+	LDA	$1234,X
+	LDX	$1234,Y
+	CPY	#$12
+	CMP	($12,X)
+	CPY	$12
+	CMP	$12
+	DEC	$12
+	INY
+	CMP	#$12
+	DEX
+	CPY	$1234
+	CMP	$1234
+	DEC	$1234
+BNE_:
+	BNE	BNE_
+	CMP	($12),Y
+	CMP	$12,X
+	DEC	$12,X
+	CLD
+	CMP	$1234,Y
+	CMP	$1234,X
+	DEC	$1234,X
+	CPX	#$12
+	SBC	($12,X)
+	CPX	$12
+	SBC	$12
+	INC	$12
+	INX
+	SBC	#$12
+	NOP
+	CPX	$1234
+	SBC	$1234
+	INC	$1234
+BEQ_:
+	BEQ	BEQ_
+	SBC	($12),Y
+	SBC	$12,X
+	INC	$12,X
+	SED
+	SBC	$1234,Y
+	SBC	$1234,X
+	INC	$1234,X
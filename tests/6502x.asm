@@ -0,0 +1,18 @@
+ processor 6502x
+ org $1000
+ LAX 5
+ LAX $1234,Y
+ SAX 5,Y
+ DCP (5),Y
+ ISC $1234,X
+ SLO 5
+ ANC #1
+ ALR #2
+ SBX #3
+ NOP
+ NOP #1
+ NOP 5
+ NOP $1234,X
+ XAA #1
+ TAS $1234,Y
+ LDA #1
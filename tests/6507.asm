@@ -0,0 +1,11 @@
+ processor 6507
+ org $F000
+Start:
+ STA WSYNC
+ LDA INTIM
+ STA TIM64T
+ JMP Start
+ fold
+ JMP Start
+ LDA $1080
+ BIT $2002
@@ -0,0 +1,43 @@
+ processor 65816
+ org $8000
+Start:
+ CLC
+ XCE
+ REP #$30
+ LDA #$1234
+ LDX #$5678
+ SEP #$20
+ LDA #$12
+ LDY #$1234
+ SEP #$10
+ LDY #$12
+ a16
+ i16
+ CMP #1
+ CPX #2
+ a8
+ i8
+ AND #3
+ LDA $123456
+ LDA $123456,X
+ STA $12
+ STA $1234
+ LDA [$12]
+ LDA [$12],Y
+ LDA 3,S
+ LDA (3,S),Y
+ JML [$1234]
+ JML $123456
+ JSL $018000
+ JSR ($1234,X)
+ MVN 1,2
+ MVP $7E,$7F
+ PEA $1234
+ PEI ($12)
+ PER Start
+ BRL Start
+ COP #5
+ WDM #0
+ XBA
+ RTL
+ BRA Start
@@ -0,0 +1,22 @@
+ processor 8048
+ org $400
+Start: MOV A,#$12
+ MOV R3,#5
+ MOV @R1,A
+ MOVP A,@A
+ JMPP @A
+ ADD A,R7
+ ANL P1,#$F0
+ OUTL P2,A
+ MOVD P5,A
+ CLR F0
+ CALL Sub
+ DJNZ R2,Start
+ JB 3,Start
+ JNZ Start
+ SEL RB1
+ STRT T
+ JMP $C00
+Sub: RET
+ SEL MB1
+ JMP $C00
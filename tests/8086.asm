@@ -0,0 +1,88 @@
+ processor 8086
+ org $100
+Start:
+ MOV AX,CS
+ MOV DS,AX
+ MOV ES,AX
+ MOV AL,[Data]
+ MOV [Data],AX
+ MOV BX,Data
+ MOV CL,5
+ MOV BYTE [BX+SI+4],$12
+ MOV WORD [BP],$1234
+ MOV DX,[BP+DI-2]
+ MOV [ES:DI+300],CX
+ MOV AX,[SS:Data]
+ ADD AX,BX
+ ADD AL,5
+ ADD AX,5
+ ADD AX,$1234
+ ADD SI,-1
+ SUB WORD [Data],3
+ CMP BYTE [SI],0
+ XOR CX,[BX+Data]
+ AND DL,DH
+ TEST AL,$80
+ TEST [BX],DX
+ TEST WORD [DI],$100
+ INC AX
+ INC BYTE [BX]
+ DEC DI
+ NEG CX
+ MUL BL
+ IDIV WORD [Data]
+ SHL AX,1
+ ROR BYTE [BX],CL
+ SAR DX,CL
+ XCHG AX,CX
+ XCHG BL,[SI]
+ PUSH ES
+ PUSH AX
+ PUSH WORD [Data]
+ POP DS
+ POP BX
+ POP WORD [BX]
+ IN AL,$60
+ IN AX,DX
+ OUT DX,AL
+ OUT $20,AL
+ LEA SI,[BX+DI+8]
+ LES DI,[Data]
+ LDS SI,[BX]
+ INT $21
+ INT3
+ AAM
+ AAD 16
+ REP MOVSB
+ REPNE SCASW
+ CLD
+ ES
+ LODSB
+Loop:
+ LOOP Loop
+ JCXZ Loop
+ JE Near
+ JNE Loop
+ JMP Loop
+ JMP Near
+ JMP SHORT Loop
+ JMP NEAR Loop
+ JMP BX
+ JMP [Data]
+ JMP FAR [BX]
+ JMP $FFFF:0
+ CALL Sub
+ CALL [BX+2]
+ CALL FAR [Data]
+ CALL $F000:$E05B
+Sub:
+ RET
+ RET 4
+ RETF
+ org $300
+Near:
+ NOP
+ JC Start
+Data:
+ word $1234, Start
+ byte 1
@@ -0,0 +1,26 @@
+ processor cp1610
+ org $5000
+ bidecle Start
+ decle 0,1,2
+Start: MVII #$1234, R0
+ SDBD
+ MVII #$1234, R4
+ MVO R0, $200
+ MVI@ R4, R1
+ MVO@ R1, R5
+ ADDR R1, R2
+ CLRR R3
+ TSTR R0
+ SLL R0, 2
+ PSHR R5
+ PULR R7
+ JR R5
+ JSR R5, Start
+ J Loop
+Loop: B Loop
+ BNEQ Fwd
+Fwd: NOP
+ romw 10
+ MVII #1023, R0
+ org $5030
+ HLT
@@ -0,0 +1,18 @@
+ processor f8
+ org $0800
+ cartridge
+Start:
+	CLR
+	LIS	3
+	LR	0,A
+	LR	A,KU
+	DCI	Data
+	LM
+	INC
+	OUTS	1
+	BNZ	Start
+	PI	Start
+	POP
+	JMP	Start
+Data:
+ byte 1,2
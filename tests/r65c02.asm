@@ -0,0 +1,36 @@
+ processor r65c02
+ org $8000
+Start:
+ LDA #1
+ LDA 5
+ LDA 5,X
+ LDX 5,Y
+ LDA $1234,Y
+ LDA (5),Y
+ LDA (5,X)
+ LDA (5)
+ STA (5)
+ JMP ($1234)
+ JMP ($1234,X)
+ BIT #$80
+ BIT 5,X
+ INC A
+ DEC
+ ASL
+ ROL A
+ STZ 5
+ STZ $1234,X
+ TSB 5
+ TRB $1234
+ PHX
+ PLY
+ BRA Start
+ RMB3 5
+ SMB7 $12
+ BBR0 5,Start
+ BBS7 5,Fwd
+Fwd:
+ LDA Fwd
+ STA Zp
+ org $8100
+Zp:
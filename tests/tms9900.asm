@@ -0,0 +1,31 @@
+ processor tms9900
+ org $6000
+ byte $AA, 1
+ data Start
+Start:
+ LWPI $8300
+ LIMI 0
+ LI R0,$1234
+ MOV R1,R2
+ MOV *R1,@Dest
+ MOVB *R3+,@Dest(R4)
+ A @Dest,R5
+ CLR *R10
+ SRL R1,8
+ COC @Dest,R3
+ XOP *R2,1
+ LDCR R1,16
+ STCR @Dest,8
+ SBO 3
+ TB -1
+ JMP Start
+ JNE Loop
+Loop:
+ BL @Sub
+ RT
+ NOP
+Sub:
+ byte 1
+ even
+Dest:
+ data -1, 2
@@ -0,0 +1,386 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Texas Instruments TMS9900 back end, as used in the TI-99/4A
+//
+// The 16 registers R0-R15 live in memory, in a workspace pointed to by
+// the WP register. Memory is byte-addressed but instructions are made of
+// 16-bit words, stored high byte first, which must sit at even addresses.
+// General operands are a register (R3), indirect (*R3), indirect with
+// auto-increment (*R3+), symbolic (@LABEL) or indexed (@LABEL(R3)), the
+// last two followed by an extra word holding the address. Operands are
+// written source first, destination last.
+
+use crate::{Assembly, Backend, Expression, Instruction};
+
+pub struct Tms9900Assembler {}
+
+// The instruction formats, as numbered in the TMS9900 data manual
+enum Form {
+    // Format VII, and the pseudo-instructions that stand for one word
+    Implied,
+    // Format I, two general operands, with whether they're words
+    TwoGeneral(bool),
+    // Format II jumps, relative to the next instruction
+    Jump,
+    // Format II CRU bit instructions, with a signed displacement
+    CruBit,
+    // Formats III and IX, a general source and a register destination
+    GeneralRegister,
+    // Format IX XOP, a general source and a software trap number
+    ExtendedOperation,
+    // Format IV, a general operand and a bit count
+    Cru,
+    // Format V, a register and a shift count
+    Shift,
+    // Format VI, a single general operand, with whether it's a word
+    Single(bool),
+    // Format VIII, a register and an immediate word
+    RegisterImmediate,
+    // Format VIII, an immediate word alone
+    Immediate,
+    // Format VIII, a register alone
+    Register,
+}
+
+impl Backend for Tms9900Assembler {
+    fn instruction(&mut self, asm: &mut Assembly, i: &Instruction) -> bool {
+        use Form::*;
+
+        let (opcode, form) = match i.mnemonic.as_str() {
+            "data" => {
+                self.data(asm, i);
+                return true;
+            }
+            "even" => {
+                asm.no_parameter(i);
+                if !asm.address.is_multiple_of(2) {
+                    asm.emit_byte(0);
+                }
+                return true;
+            }
+            "LI" => (0x0200, RegisterImmediate),
+            "AI" => (0x0220, RegisterImmediate),
+            "ANDI" => (0x0240, RegisterImmediate),
+            "ORI" => (0x0260, RegisterImmediate),
+            "CI" => (0x0280, RegisterImmediate),
+            "STWP" => (0x02A0, Register),
+            "STST" => (0x02C0, Register),
+            "LWPI" => (0x02E0, Immediate),
+            "LIMI" => (0x0300, Immediate),
+            "IDLE" => (0x0340, Implied),
+            "RSET" => (0x0360, Implied),
+            "RTWP" => (0x0380, Implied),
+            "CKON" => (0x03A0, Implied),
+            "CKOF" => (0x03C0, Implied),
+            "LREX" => (0x03E0, Implied),
+            "BLWP" => (0x0400, Single(true)),
+            "B" => (0x0440, Single(true)),
+            "X" => (0x0480, Single(true)),
+            "CLR" => (0x04C0, Single(true)),
+            "NEG" => (0x0500, Single(true)),
+            "INV" => (0x0540, Single(true)),
+            "INC" => (0x0580, Single(true)),
+            "INCT" => (0x05C0, Single(true)),
+            "DEC" => (0x0600, Single(true)),
+            "DECT" => (0x0640, Single(true)),
+            "BL" => (0x0680, Single(true)),
+            "SWPB" => (0x06C0, Single(true)),
+            "SETO" => (0x0700, Single(true)),
+            "ABS" => (0x0740, Single(true)),
+            "SRA" => (0x0800, Shift),
+            "SRL" => (0x0900, Shift),
+            "SLA" => (0x0A00, Shift),
+            "SRC" => (0x0B00, Shift),
+            // B *R11, the usual return from BL
+            "RT" => (0x045B, Implied),
+            // JMP to the next instruction
+            "NOP" => (0x1000, Implied),
+            "JMP" => (0x1000, Jump),
+            "JLT" => (0x1100, Jump),
+            "JLE" => (0x1200, Jump),
+            "JEQ" => (0x1300, Jump),
+            "JHE" => (0x1400, Jump),
+            "JGT" => (0x1500, Jump),
+            "JNE" => (0x1600, Jump),
+            "JNC" => (0x1700, Jump),
+            "JOC" => (0x1800, Jump),
+            "JNO" => (0x1900, Jump),
+            "JL" => (0x1A00, Jump),
+            "JH" => (0x1B00, Jump),
+            "JOP" => (0x1C00, Jump),
+            "SBO" => (0x1D00, CruBit),
+            "SBZ" => (0x1E00, CruBit),
+            "TB" => (0x1F00, CruBit),
+            "COC" => (0x2000, GeneralRegister),
+            "CZC" => (0x2400, GeneralRegister),
+            "XOR" => (0x2800, GeneralRegister),
+            "XOP" => (0x2C00, ExtendedOperation),
+            "LDCR" => (0x3000, Cru),
+            "STCR" => (0x3400, Cru),
+            "MPY" => (0x3800, GeneralRegister),
+            "DIV" => (0x3C00, GeneralRegister),
+            "SZC" => (0x4000, TwoGeneral(true)),
+            "SZCB" => (0x5000, TwoGeneral(false)),
+            "S" => (0x6000, TwoGeneral(true)),
+            "SB" => (0x7000, TwoGeneral(false)),
+            "C" => (0x8000, TwoGeneral(true)),
+            "CB" => (0x9000, TwoGeneral(false)),
+            "A" => (0xA000, TwoGeneral(true)),
+            "AB" => (0xB000, TwoGeneral(false)),
+            "MOV" => (0xC000, TwoGeneral(true)),
+            "MOVB" => (0xD000, TwoGeneral(false)),
+            "SOC" => (0xE000, TwoGeneral(true)),
+            "SOCB" => (0xF000, TwoGeneral(false)),
+            _ => return false,
+        };
+        asm.check(
+            asm.address.is_multiple_of(2),
+            &format!("{} at odd address {}", i.mnemonic, asm.address),
+        );
+        match form {
+            Implied => {
+                asm.no_parameter(i);
+                asm.emit_words(&i.mnemonic, &[opcode], None);
+            }
+            TwoGeneral(word) => {
+                let (source, destination) = self.two_parameters(asm, i);
+                let (s, s_extra) = self.general(asm, i, source, word);
+                let (d, d_extra) = self.general(asm, i, destination, word);
+                let mut words = vec![opcode | d << 6 | s];
+                words.extend(s_extra);
+                words.extend(d_extra);
+                let parameter = s_extra.or(d_extra).map(i64::from);
+                asm.emit_words(&i.mnemonic, &words, parameter);
+            }
+            Jump => {
+                let destination = asm.value(asm.parameter(i));
+                asm.check(
+                    destination % 2 == 0,
+                    &format!("{} to odd address {}", i.mnemonic, destination),
+                );
+                let offset = (destination - i64::from(asm.address) - 2) / 2;
+                asm.check(
+                    (-128..=127).contains(&offset),
+                    &format!("jump out of range for {}", i.mnemonic),
+                );
+                asm.emit_words(
+                    &i.mnemonic,
+                    &[opcode | (offset & 255) as u16],
+                    Some(offset & 255),
+                );
+            }
+            CruBit => {
+                let p = asm.value(asm.parameter(i));
+                asm.check(
+                    (-128..=127).contains(&p),
+                    &format!("invalid CRU displacement for {}", i.mnemonic),
+                );
+                asm.emit_words(&i.mnemonic, &[opcode | (p & 255) as u16], Some(p));
+            }
+            GeneralRegister => {
+                let (source, destination) = self.two_parameters(asm, i);
+                let (s, extra) = self.general(asm, i, source, true);
+                let d = self.register(asm, i, destination);
+                self.emit_general(asm, i, opcode | d << 6 | s, extra);
+            }
+            ExtendedOperation => {
+                let (source, number) = self.two_parameters(asm, i);
+                let (s, extra) = self.general(asm, i, source, true);
+                let n = self.count(asm, i, number, 0, 15);
+                self.emit_general(asm, i, opcode | n << 6 | s, extra);
+            }
+            Cru => {
+                let (source, count) = self.two_parameters(asm, i);
+                // A count of 16 is encoded as 0, and up to 8 bits
+                // are transferred as a byte
+                let c = self.count(asm, i, count, 1, 16);
+                let (s, extra) = self.general(asm, i, source, c > 8);
+                self.emit_general(asm, i, opcode | (c & 15) << 6 | s, extra);
+            }
+            Shift => {
+                let (register, count) = self.two_parameters(asm, i);
+                let r = self.register(asm, i, register);
+                // A count of 0 takes the count from R0 instead
+                let c = self.count(asm, i, count, 0, 15);
+                asm.emit_words(&i.mnemonic, &[opcode | c << 4 | r], None);
+            }
+            Single(word) => {
+                let (s, extra) = self.general(asm, i, asm.parameter(i), word);
+                self.emit_general(asm, i, opcode | s, extra);
+            }
+            RegisterImmediate => {
+                let (register, value) = self.two_parameters(asm, i);
+                let r = self.register(asm, i, register);
+                let v = self.immediate(asm, i, value);
+                asm.emit_words(&i.mnemonic, &[opcode | r, v], Some(i64::from(v)));
+            }
+            Immediate => {
+                let v = self.immediate(asm, i, asm.parameter(i));
+                asm.emit_words(&i.mnemonic, &[opcode, v], Some(i64::from(v)));
+            }
+            Register => {
+                let r = self.register(asm, i, asm.parameter(i));
+                asm.emit_words(&i.mnemonic, &[opcode | r], None);
+            }
+        }
+        true
+    }
+
+    // There's no single-byte NOP, fill with zeroes
+    fn fill(&self) -> u16 {
+        0x00
+    }
+}
+
+impl Tms9900Assembler {
+    // 16-bit values, which must be word-aligned
+    fn data(&self, asm: &mut Assembly, i: &Instruction) {
+        if i.parameters.is_empty() {
+            asm.error("missing parameter for data");
+        }
+        asm.check(
+            asm.address.is_multiple_of(2),
+            &format!("data at odd address {}", asm.address),
+        );
+        for p in &i.parameters {
+            if let Expression::Immediate(_) = p {
+                asm.error("wrong parameter type for data");
+            }
+            let v = asm.value(p);
+            asm.check((-32768..=65535).contains(&v), "invalid value for data");
+            asm.emit_word((v & 0xFFFF) as u16);
+        }
+    }
+
+    fn two_parameters<'i>(
+        &self,
+        asm: &Assembly,
+        i: &'i Instruction,
+    ) -> (&'i Expression, &'i Expression) {
+        match i.parameters.as_slice() {
+            [a, b] => (a, b),
+            _ => asm.error(&format!("wrong number of parameters for {}", i.mnemonic)),
+        }
+    }
+
+    // Emit an instruction with one general operand, and the address
+    // that follows it, if any
+    fn emit_general(&self, asm: &mut Assembly, i: &Instruction, opcode: u16, extra: Option<u16>) {
+        match extra {
+            Some(a) => asm.emit_words(&i.mnemonic, &[opcode, a], Some(i64::from(a))),
+            None => asm.emit_words(&i.mnemonic, &[opcode], None),
+        }
+    }
+
+    // Workspace register, either as R0-R15 or as a plain expression
+    fn register(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> u16 {
+        if let Expression::Identifier(s) = e {
+            if let Some(r) = s.strip_prefix('R') {
+                if let Ok(r) = r.parse::<u16>() {
+                    if r <= 15 {
+                        return r;
+                    }
+                }
+            }
+        }
+        match e {
+            Expression::Immediate(_)
+            | Expression::Indirect(_)
+            | Expression::Deferred(_)
+            | Expression::PostIncrement(_)
+            | Expression::PostDecrement(_) => {
+                asm.error(&format!("wrong parameter type for {}", i.mnemonic))
+            }
+            _ => (),
+        }
+        let r = asm.value(e);
+        asm.check(
+            (0..=15).contains(&r),
+            &format!("invalid register for {}", i.mnemonic),
+        );
+        (r & 15) as u16
+    }
+
+    // General operand, as the addressing mode and register in the low
+    // 6 bits of the opcode, followed by the address word, if any
+    fn general(
+        &self,
+        asm: &Assembly,
+        i: &Instruction,
+        e: &Expression,
+        word: bool,
+    ) -> (u16, Option<u16>) {
+        match e {
+            Expression::Deferred(e) => match &**e {
+                Expression::PostIncrement(r) => (0x30 | self.register(asm, i, r), None),
+                r => (0x10 | self.register(asm, i, r), None),
+            },
+            Expression::Indirect(e) => match &**e {
+                Expression::Indexed(a, r) => {
+                    let r = self.register(asm, i, r);
+                    // Index register 0 would mean a symbolic address
+                    asm.check(
+                        r != 0,
+                        &format!("R0 can't be an index register for {}", i.mnemonic),
+                    );
+                    (0x20 | r, Some(self.address(asm, i, a)))
+                }
+                a => {
+                    let a = self.address(asm, i, a);
+                    if word && !a.is_multiple_of(2) {
+                        asm.warning(&format!(
+                            "word operand of {} at odd address {}",
+                            i.mnemonic, a
+                        ));
+                    }
+                    (0x20, Some(a))
+                }
+            },
+            r => (self.register(asm, i, r), None),
+        }
+    }
+
+    fn address(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> u16 {
+        let a = asm.value(e);
+        asm.check(
+            (-32768..=65535).contains(&a),
+            &format!("invalid address for {}", i.mnemonic),
+        );
+        (a & 0xFFFF) as u16
+    }
+
+    // Immediate word, where the immediate marker is optional
+    fn immediate(&self, asm: &Assembly, i: &Instruction, e: &Expression) -> u16 {
+        let v = match e {
+            Expression::Immediate(e) => asm.value(e),
+            e => asm.value(e),
+        };
+        asm.check(
+            (-32768..=65535).contains(&v),
+            &format!("invalid parameter value for {}", i.mnemonic),
+        );
+        (v & 0xFFFF) as u16
+    }
+
+    // Small number in a field of the opcode
+    fn count(&self, asm: &Assembly, i: &Instruction, e: &Expression, min: i64, max: i64) -> u16 {
+        let c = asm.value(e);
+        asm.check(
+            (min..=max).contains(&c),
+            &format!("invalid count for {}", i.mnemonic),
+        );
+        (c & 31) as u16
+    }
+}
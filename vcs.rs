@@ -0,0 +1,245 @@
+// Copyright 2022 Jean-Baptiste M. "JBQ" "Djaybee" Queru
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Bank switching on the Atari 2600
+//
+// bankswitch selects a scheme and defines the hotspot symbols. Each
+// bank n, address starts a bank, which is laid out after the previous
+// one in the ROM image but assembled at its window in the address
+// space. By default, that's the top of memory for F8, F6 and F4, $FC00
+// for the fixed last bank of E0, whose other banks need a window, $F000
+// for the switched slice of 3F, whose last bank is fixed at $F800, and
+// $F000 and $D000 for FE, where bit 13 of the address selects the
+// bank. Banks are padded to their full size, and end with the reset
+// and IRQ vectors given by vectors.
+
+use crate::{Assembly, Expression, Instruction};
+
+struct Scheme {
+    name: &'static str,
+    // Size of each bank, in bytes
+    size: u32,
+    // Number of banks, if fixed
+    count: Option<u32>,
+    // First address that selects a bank when it's accessed
+    hotspot: i64,
+}
+
+const SCHEMES: [Scheme; 6] = [
+    Scheme {
+        name: "F8",
+        size: 4096,
+        count: Some(2),
+        hotspot: 0x1FF8,
+    },
+    Scheme {
+        name: "F6",
+        size: 4096,
+        count: Some(4),
+        hotspot: 0x1FF6,
+    },
+    Scheme {
+        name: "F4",
+        size: 4096,
+        count: Some(8),
+        hotspot: 0x1FF4,
+    },
+    Scheme {
+        name: "E0",
+        size: 1024,
+        count: Some(8),
+        hotspot: 0x1FE0,
+    },
+    Scheme {
+        name: "3F",
+        size: 2048,
+        count: None,
+        hotspot: 0x3F,
+    },
+    Scheme {
+        name: "FE",
+        size: 4096,
+        count: Some(2),
+        hotspot: 0,
+    },
+];
+
+pub struct Bankswitch {
+    scheme: Option<&'static Scheme>,
+    // Current bank, and its window in the address space
+    bank: Option<(u32, u32)>,
+    // Reset and IRQ vectors of the current bank
+    vectors: Option<(i64, i64)>,
+}
+
+impl Bankswitch {
+    pub fn new() -> Bankswitch {
+        Bankswitch {
+            scheme: None,
+            bank: None,
+            vectors: None,
+        }
+    }
+
+    // Handle the bankswitch, bank and vectors directives
+    pub fn directive(&mut self, asm: &mut Assembly, i: &Instruction, fill: u16) -> bool {
+        match i.mnemonic.as_str() {
+            "bankswitch" => self.scheme(asm, i),
+            "bank" => self.bank(asm, i, fill),
+            "vectors" => {
+                if self.bank.is_none() {
+                    asm.error("vectors outside of a bank");
+                }
+                if self.vectors.is_some() {
+                    asm.error("duplicate vectors");
+                }
+                self.vectors = match i.parameters.as_slice() {
+                    [r] => Some((asm.value(r), asm.value(r))),
+                    [r, q] => Some((asm.value(r), asm.value(q))),
+                    _ => asm.error("invalid parameters for vectors"),
+                };
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn scheme(&mut self, asm: &mut Assembly, i: &Instruction) {
+        if self.scheme.is_some() {
+            asm.error("duplicate bankswitch");
+        }
+        let scheme = match asm.parameter(i) {
+            Expression::Identifier(n) => match SCHEMES.iter().find(|s| s.name == n) {
+                Some(s) => s,
+                None => asm.error(&format!("unknown bank switching scheme: {}", n)),
+            },
+            _ => asm.error("invalid parameter for bankswitch"),
+        };
+        match scheme.name {
+            "F8" | "F6" | "F4" => {
+                for b in 0..scheme.count.unwrap() {
+                    asm.define(&format!("BANK{}", b), scheme.hotspot + i64::from(b));
+                }
+            }
+            // One group of hotspots for each of the three switched slices
+            "E0" => {
+                for s in 0..3 {
+                    for b in 0..8 {
+                        asm.define(&format!("SLICE{}_BANK{}", s, b), scheme.hotspot + s * 8 + b);
+                    }
+                }
+            }
+            // The bank number is written to this address
+            "3F" => asm.define("BANKSELECT", scheme.hotspot),
+            // FE switches on the stack accesses of JSR and RTS
+            _ => (),
+        }
+        self.scheme = Some(scheme);
+    }
+
+    fn bank(&mut self, asm: &mut Assembly, i: &Instruction, fill: u16) {
+        let scheme = match self.scheme {
+            Some(s) => s,
+            None => asm.error("bank without bankswitch"),
+        };
+        let expected = self.bank.map_or(0, |(b, _)| b + 1);
+        let (n, window) = match i.parameters.as_slice() {
+            [n] => match Self::window(scheme, expected) {
+                Some(w) => (asm.value(n), w),
+                None => asm.error(&format!(
+                    "bank {} needs a window on {}",
+                    expected, scheme.name
+                )),
+            },
+            [n, w] => (asm.value(n), asm.value(w)),
+            _ => asm.error("invalid parameters for bank"),
+        };
+        if n != i64::from(expected) {
+            asm.error(&format!("expected bank {}", expected));
+        }
+        if scheme.count.is_some_and(|c| expected >= c) {
+            asm.error(&format!("too many banks for {}", scheme.name));
+        }
+        if !(0..=0x10000 - i64::from(scheme.size)).contains(&window) {
+            asm.error("invalid window for bank");
+        }
+        self.close(asm, fill);
+        if asm.address == 0 {
+            asm.org(window as u32, fill);
+        } else {
+            asm.rorg(window as u32);
+        }
+        self.bank = Some((expected, window as u32));
+        asm.bank = Some(i64::from(expected));
+    }
+
+    // Default window of a bank, if it has one
+    fn window(scheme: &Scheme, n: u32) -> Option<i64> {
+        match scheme.name {
+            "E0" if n == 7 => Some(0xFC00),
+            "E0" => None,
+            "3F" => Some(0xF000),
+            "FE" if n == 0 => Some(0xF000),
+            "FE" => Some(0xD000),
+            _ => Some(0x10000 - i64::from(scheme.size)),
+        }
+    }
+
+    // Pad the current bank to its full size, ending with its vectors
+    fn close(&mut self, asm: &mut Assembly, fill: u16) {
+        let (n, window) = match self.bank {
+            Some(b) => b,
+            None => return,
+        };
+        let end = window + self.scheme.unwrap().size;
+        let target = match self.vectors {
+            Some(_) if end != 0x10000 => asm.error("vectors outside of the top of memory"),
+            Some(_) => end - 4,
+            None => end,
+        };
+        if asm.address > target {
+            asm.check(false, &format!("bank {} overflows", n));
+            return;
+        }
+        asm.pad(target, fill);
+        if let Some((reset, irq)) = self.vectors.take() {
+            for v in [reset, irq] {
+                asm.emit_byte((v & 255) as u8);
+                asm.emit_byte(((v >> 8) & 255) as u8);
+            }
+        }
+    }
+
+    // Close the last bank, and check that none is missing
+    pub fn end_pass(&mut self, asm: &mut Assembly, fill: u16) {
+        let scheme = match self.scheme {
+            Some(s) => s,
+            None => return,
+        };
+        self.close(asm, fill);
+        let banks = self.bank.map_or(0, |(b, _)| b + 1);
+        if let (Some((n, window)), "3F") = (self.bank, scheme.name) {
+            asm.check(
+                window == 0xF800,
+                &format!("last 3F bank {} needs the window $F800", n),
+            );
+        }
+        if let Some(count) = scheme.count {
+            asm.check(
+                banks == count,
+                &format!("{} needs {} banks, found {}", scheme.name, count, banks),
+            );
+        }
+    }
+}